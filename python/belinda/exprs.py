@@ -1,3 +1,4 @@
+from functools import partial
 from polars import col, when
 from polars import Expr
 import polars as pl
@@ -42,7 +43,7 @@ def edge_coverage(self, overlap=False):
     a = "edge_coverage"
     if overlap:
         return (
-            pl.col("nodes").map(lambda x: self.covered_edges_count(x)) / self.m
+            pl.col("nodes").map(self.covered_edges_count, return_dtype=pl.UInt64) / self.m
         ).alias(a)
     else:
         return (pl.col("m").sum() / self.m).alias(a)
@@ -72,26 +73,25 @@ def peek(graph, clustering, overlap=False, statistics=[pl.col('n')]):
     )
 
 
-def write_membership(graph, clustering, filepath):
-    df = graph.nodes(clustering)
-    with open(filepath, "w+") as fh:
-        for n, lbls in zip(df.get_column("node"), df.get_column("labels")):
-            for l in lbls:
-                fh.write(f"{n}\t{l}\n")
-
-def write_json(graph, clustering, filepath):
-    clus = clustering.with_column(pl.col('nodes').set.flatten(graph).alias('nodes'))
-    clus.write_ndjson(filepath)
-
 setattr(Graph, "modularity", modularity)
 setattr(Graph, "cpm", lambda self, r: cpm(r))
 setattr(
-    Graph, "intra_edges", lambda self, exprs: exprs.map(lambda x: self.covered_edges(x))
+    Graph,
+    "intra_edges",
+    lambda self, exprs: exprs.map(self.covered_edges, return_dtype=pl.Binary),
 )
 setattr(Graph, "conductance", conductance)
 setattr(Graph, "vol1", vol1)
-setattr(Graph, "cc", lambda self, exprs: exprs.map(lambda x: cc_labels(self, x)))
-setattr(Graph, "cc_size", lambda self, exprs: exprs.map(lambda x: cc_size(self, x)))
+setattr(
+    Graph,
+    "cc",
+    lambda self, exprs: exprs.map(partial(cc_labels, self), return_dtype=pl.UInt32),
+)
+setattr(
+    Graph,
+    "cc_size",
+    lambda self, exprs: exprs.map(partial(cc_size, self), return_dtype=pl.UInt32),
+)
 setattr(
     Graph,
     "annotate_cc",
@@ -117,15 +117,81 @@ class EfficientSet:
 
     def popcnt(self):
         """Count the number of elements in the set."""
-        return self._expr.map(popcnt)
+        return self._expr.map(popcnt, return_dtype=pl.UInt32)
 
     def len(self):
         """Count the number of elements in the set."""
-        return self._expr.map(popcnt)
+        return self._expr.map(popcnt, return_dtype=pl.UInt32)
 
     def union(self):
-        """Compute the union of all sets in the series."""
-        return self._expr.map(lambda x: union(x))
+        """Compute the union of all sets in the series, or of each group inside `groupby().agg()`."""
+        return self._expr.map(union, return_dtype=pl.Binary, agg_list=True)
 
     def flatten(self, g):
-        return self._expr.map(lambda x: nodeset_to_list(g, x))
+        return self._expr.map(partial(nodeset_to_list, g), return_dtype=pl.List(pl.UInt32))
+
+    def repr(self, g=None, limit=5):
+        """A readable summary of each set: its size and its first `limit` members
+        (as original node ids if the graph `g` is given)."""
+        return self._expr.map(partial(set_repr, g=g, limit=limit), return_dtype=pl.Utf8)
+
+    def contains(self, g, node_id):
+        """Whether each set contains the node `node_id` (an original node id)."""
+        return self._expr.map(partial(contains, g, node=node_id), return_dtype=pl.Boolean)
+
+    def contains_any(self, g, node_ids):
+        """Whether each set contains at least one of `node_ids` (original node ids)."""
+        node_ids = pl.Series("node", node_ids, dtype=pl.UInt32)
+        return self._expr.map(partial(contains_any, g, nodes=node_ids), return_dtype=pl.Boolean)
+
+    def min(self, g):
+        """The smallest member of each set (by internal id) as an original node id, null for empty sets."""
+        return self._expr.map(partial(set_min, g), return_dtype=pl.UInt32)
+
+    def max(self, g):
+        """The largest member of each set (by internal id) as an original node id, null for empty sets."""
+        return self._expr.map(partial(set_max, g), return_dtype=pl.UInt32)
+
+    def select(self, g, rank):
+        """The member of rank `rank` (0 being the smallest internal id) of each set as an original node id,
+        null for sets with at most `rank` members."""
+        return self._expr.map(partial(set_select, g, rank=rank), return_dtype=pl.UInt32)
+
+    def sample(self, g, k, seed=0):
+        """`k` members of each set drawn at random (all of them for smaller sets) as original node ids.
+        The same `seed` gives the same samples."""
+        return self._expr.map(partial(set_sample, g, k=k, seed=seed), return_dtype=pl.List(pl.UInt32))
+
+    def _pairwise(self, f, other, return_dtype):
+        return pl.map([self._expr, other], lambda s: f(*s), return_dtype=return_dtype)
+
+    def intersection(self, other=None):
+        """Element-wise intersection with the sets in `other`. Without `other`, compute the
+        intersection of all sets in the series, or of each group inside `groupby().agg()`."""
+        if other is None:
+            return self._expr.map(intersection_all, return_dtype=pl.Binary, agg_list=True)
+        return self._pairwise(intersection, other, pl.Binary)
+
+    def difference(self, other):
+        """Element-wise difference, i.e., elements not in the sets of `other`."""
+        return self._pairwise(difference, other, pl.Binary)
+
+    def symmetric_difference(self, other):
+        """Element-wise symmetric difference with the sets in `other`."""
+        return self._pairwise(symmetric_difference, other, pl.Binary)
+
+    def is_subset(self, other):
+        """Whether each set is a subset of the corresponding set in `other`."""
+        return self._pairwise(is_subset, other, pl.Boolean)
+
+    def intersection_len(self, other):
+        """Element-wise size of the intersection with the sets in `other`."""
+        return self._pairwise(intersection_len, other, pl.UInt64)
+
+    def jaccard(self, other):
+        """Element-wise Jaccard index with the sets in `other` (null if both are empty)."""
+        return self._pairwise(jaccard, other, pl.Float64)
+
+    def overlap_coefficient(self, other):
+        """Element-wise overlap coefficient with the sets in `other` (null if either is empty)."""
+        return self._pairwise(overlap_coefficient, other, pl.Float64)
@@ -1,7 +1,10 @@
 use polars::export::arrow::array::{Array, BinaryArray, MutableBinaryArray};
-use polars::prelude::PolarsError;
+use polars::prelude::{BinaryChunked, DataType};
 use polars::{series::Series};
 use roaring::{MultiOps, RoaringBitmap, RoaringTreemap};
+use std::borrow::Cow;
+
+use crate::errors::Error;
 
 pub type ArrayRef = Box<dyn Array>;
 
@@ -17,53 +20,162 @@ impl EfficientSet {
             EfficientSet::BigSet(set) => set.len() as u64,
         }
     }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = u64> + '_> {
+        match self {
+            EfficientSet::SmallSet(set) => Box::new(set.iter().map(u64::from)),
+            EfficientSet::BigSet(set) => Box::new(set.iter()),
+        }
+    }
+
+    pub fn contains(&self, value: u64) -> bool {
+        match self {
+            EfficientSet::SmallSet(set) => u32::try_from(value).map_or(false, |v| set.contains(v)),
+            EfficientSet::BigSet(set) => set.contains(value),
+        }
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        match self {
+            EfficientSet::SmallSet(set) => set.max().map(u64::from),
+            EfficientSet::BigSet(set) => set.max(),
+        }
+    }
+
+    pub fn is_small(&self) -> bool {
+        matches!(self, EfficientSet::SmallSet(_))
+    }
+
+    /// The set as a `RoaringTreemap`, promoting a `SmallSet` if needed
+    pub fn to_treemap(&self) -> Cow<'_, RoaringTreemap> {
+        match self {
+            EfficientSet::SmallSet(set) => Cow::Owned(RoaringTreemap::from_bitmaps(
+                std::iter::once((0, set.clone())),
+            )),
+            EfficientSet::BigSet(set) => Cow::Borrowed(set),
+        }
+    }
+
+    /// The members below 2^32, i.e., those that can be node ids
+    pub fn low_bits(&self) -> Cow<'_, RoaringBitmap> {
+        match self {
+            EfficientSet::SmallSet(set) => Cow::Borrowed(set),
+            EfficientSet::BigSet(set) => match set.bitmaps().next() {
+                Some((0, low)) => Cow::Borrowed(low),
+                _ => Cow::Owned(RoaringBitmap::new()),
+            },
+        }
+    }
+
+    /// Applies a binary operation, promoting both sides to `BigSet` unless both are `SmallSet`
+    fn combine(
+        &self,
+        other: &EfficientSet,
+        small: fn(&RoaringBitmap, &RoaringBitmap) -> RoaringBitmap,
+        big: fn(&RoaringTreemap, &RoaringTreemap) -> RoaringTreemap,
+    ) -> EfficientSet {
+        match (self, other) {
+            (EfficientSet::SmallSet(a), EfficientSet::SmallSet(b)) => {
+                EfficientSet::SmallSet(small(a, b))
+            }
+            _ => EfficientSet::BigSet(big(&self.to_treemap(), &other.to_treemap())),
+        }
+    }
+
+    pub fn intersection(&self, other: &EfficientSet) -> EfficientSet {
+        self.combine(other, |a, b| a & b, |a, b| a & b)
+    }
+
+    pub fn difference(&self, other: &EfficientSet) -> EfficientSet {
+        self.combine(other, |a, b| a - b, |a, b| a - b)
+    }
+
+    pub fn symmetric_difference(&self, other: &EfficientSet) -> EfficientSet {
+        self.combine(other, |a, b| a ^ b, |a, b| a ^ b)
+    }
+
+    pub fn intersection_len(&self, other: &EfficientSet) -> u64 {
+        match (self, other) {
+            (EfficientSet::SmallSet(a), EfficientSet::SmallSet(b)) => a.intersection_len(b),
+            _ => self.to_treemap().intersection_len(&other.to_treemap()),
+        }
+    }
+
+    pub fn is_disjoint(&self, other: &EfficientSet) -> bool {
+        match (self, other) {
+            (EfficientSet::SmallSet(a), EfficientSet::SmallSet(b)) => a.is_disjoint(b),
+            _ => self.to_treemap().is_disjoint(&other.to_treemap()),
+        }
+    }
+
+    pub fn is_subset(&self, other: &EfficientSet) -> bool {
+        match (self, other) {
+            (EfficientSet::SmallSet(a), EfficientSet::SmallSet(b)) => a.is_subset(b),
+            _ => self.to_treemap().is_subset(&other.to_treemap()),
+        }
+    }
 }
 
 pub trait VecEfficientSet {
     fn union(self) -> EfficientSet;
-    fn to_series(self) -> Series;
+    fn intersection(self) -> EfficientSet;
+    fn to_series(self, fingerprint: u64) -> Series;
 }
 
 impl VecEfficientSet for Vec<EfficientSet> {
     fn union(self) -> EfficientSet {
-        let _smallset: Vec<EfficientSet> = vec![];
-        let _bigset: Vec<EfficientSet> = vec![];
-        let (smallset, bigset): (Vec<_>, Vec<_>) = self.into_iter().partition(|s| match s {
-            EfficientSet::SmallSet(_) => true,
-            EfficientSet::BigSet(_) => false,
-        });
-        if !smallset.is_empty() {
+        if self.iter().all(EfficientSet::is_small) {
             EfficientSet::SmallSet(
-                smallset
-                    .into_iter()
+                self.into_iter()
                     .map(|it| it.try_into().unwrap())
                     .collect::<Vec<RoaringBitmap>>()
                     .union(),
             )
         } else {
             EfficientSet::BigSet(
-                bigset
-                    .into_iter()
-                    .map(|it| it.try_into().unwrap())
+                self.into_iter()
+                    .map(RoaringTreemap::from)
                     .collect::<Vec<RoaringTreemap>>()
                     .union(),
             )
         }
     }
 
-    fn to_series(self) -> Series {
-        build_series_from_sets(self)
+    fn intersection(self) -> EfficientSet {
+        if self.iter().all(EfficientSet::is_small) {
+            EfficientSet::SmallSet(
+                self.into_iter()
+                    .map(|it| it.try_into().unwrap())
+                    .collect::<Vec<RoaringBitmap>>()
+                    .intersection(),
+            )
+        } else {
+            EfficientSet::BigSet(
+                self.into_iter()
+                    .map(RoaringTreemap::from)
+                    .collect::<Vec<RoaringTreemap>>()
+                    .intersection(),
+            )
+        }
+    }
+
+    fn to_series(self, fingerprint: u64) -> Series {
+        build_series_from_sets(self, fingerprint)
     }
 }
 
+/// Demotes a `BigSet` if all its members are below 2^32
 impl TryFrom<EfficientSet> for RoaringBitmap {
-    type Error = PolarsError;
+    type Error = Error;
 
     fn try_from(value: EfficientSet) -> Result<Self, Self::Error> {
         match value {
             EfficientSet::SmallSet(set) => Ok(set),
-            EfficientSet::BigSet(_set) => Err(PolarsError::InvalidOperation(
-                "Cannot convert BigSet to RoaringBitmap".into(),
+            EfficientSet::BigSet(_) if value.low_bits().len() == value.len() => {
+                Ok(value.low_bits().into_owned())
+            }
+            EfficientSet::BigSet(_) => Err(Error::InvalidSetColumn(
+                "set has members beyond 2^32, which cannot be node ids".into(),
             )),
         }
     }
@@ -75,15 +187,12 @@ impl From<RoaringBitmap> for EfficientSet {
     }
 }
 
-impl TryFrom<EfficientSet> for RoaringTreemap {
-    type Error = PolarsError;
-
-    fn try_from(value: EfficientSet) -> Result<Self, Self::Error> {
+/// Promotes a `SmallSet`
+impl From<EfficientSet> for RoaringTreemap {
+    fn from(value: EfficientSet) -> Self {
         match value {
-            EfficientSet::SmallSet(_set) => Err(PolarsError::InvalidOperation(
-                "Cannot convert SmallSet to RoaringTreemap".into(),
-            )),
-            EfficientSet::BigSet(set) => Ok(set),
+            EfficientSet::SmallSet(set) => RoaringTreemap::from_bitmaps(std::iter::once((0, set))),
+            EfficientSet::BigSet(set) => set,
         }
     }
 }
@@ -94,81 +203,272 @@ impl From<RoaringTreemap> for EfficientSet {
     }
 }
 
-pub fn serialize_set<W>(set: &EfficientSet, mut writer: W) -> anyhow::Result<()>
+/// Leading bytes of a serialized set: a marker and the format version. Sets written before the
+/// header existed start directly with the variant tag (0 or 1) and are still accepted.
+const SET_MAGIC: [u8; 4] = [0xB1, b'N', b'S', 1];
+
+/// What the members of a set are
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SetKind {
+    /// Written before the header existed
+    #[default]
+    Unknown,
+    /// Internal node ids
+    Nodes,
+    /// Edge ids, as computed by `Graph.covered_edges`
+    Edges,
+}
+
+impl SetKind {
+    fn from_byte(b: u8) -> anyhow::Result<SetKind> {
+        match b {
+            0 => Ok(SetKind::Unknown),
+            1 => Ok(SetKind::Nodes),
+            2 => Ok(SetKind::Edges),
+            _ => Err(anyhow::anyhow!("invalid set kind {}", b)),
+        }
+    }
+}
+
+/// Metadata stored in front of each serialized set
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SetHeader {
+    /// Whether the set is a `BigSet`
+    pub big: bool,
+    pub kind: SetKind,
+    /// Fingerprint of the graph the set refers to, 0 if unknown
+    pub fingerprint: u64,
+}
+
+pub fn serialize_set<W>(
+    set: &EfficientSet,
+    kind: SetKind,
+    fingerprint: u64,
+    mut writer: W,
+) -> anyhow::Result<()>
 where
     W: std::io::Write,
 {
+    writer.write_all(&SET_MAGIC)?;
+    writer.write_all(&[!set.is_small() as u8, kind as u8])?;
+    writer.write_all(&fingerprint.to_le_bytes())?;
     match set {
-        EfficientSet::SmallSet(bm) => {
-            writer.write_all(&[0u8])?;
-            bm.serialize_into(writer)?;
-        }
-        EfficientSet::BigSet(tm) => {
-            writer.write_all(&[1u8])?;
-            tm.serialize_into(writer)?;
-        }
+        EfficientSet::SmallSet(bm) => bm.serialize_into(writer)?,
+        EfficientSet::BigSet(tm) => tm.serialize_into(writer)?,
     }
     Ok(())
 }
 
-pub fn deserialize_set<R>(mut reader: R) -> anyhow::Result<EfficientSet>
+/// Reads the header of a serialized set, leaving `reader` at the start of the set itself
+pub fn read_header<R>(mut reader: R) -> anyhow::Result<SetHeader>
 where
     R: std::io::Read,
 {
-    let mut buf = [0u8];
-    reader.read_exact(&mut buf)?;
-    match buf[0] {
-        0 => {
-            let bm = RoaringBitmap::deserialize_from(reader)?;
-            Ok(EfficientSet::SmallSet(bm))
-        }
-        1 => {
-            let tm = RoaringTreemap::deserialize_from(reader)?;
-            Ok(EfficientSet::BigSet(tm))
-        }
-        _ => Err(anyhow::anyhow!("Invalid EfficientSet serialization")),
+    let mut tag = [0u8];
+    reader.read_exact(&mut tag)?;
+    if tag[0] == 0 || tag[0] == 1 {
+        return Ok(SetHeader {
+            big: tag[0] == 1,
+            ..Default::default()
+        });
     }
+    let mut magic = [tag[0], 0, 0, 0];
+    reader.read_exact(&mut magic[1..])?;
+    if magic[..3] == SET_MAGIC[..3] && magic[3] > SET_MAGIC[3] {
+        return Err(anyhow::anyhow!(
+            "set format version {} is newer than the supported version {}",
+            magic[3],
+            SET_MAGIC[3]
+        ));
+    }
+    if magic != SET_MAGIC {
+        return Err(anyhow::anyhow!("not a serialized set"));
+    }
+    let mut variant_and_kind = [0u8; 2];
+    reader.read_exact(&mut variant_and_kind)?;
+    let [variant, kind] = variant_and_kind;
+    let kind = SetKind::from_byte(kind)?;
+    let big = match variant {
+        0 => false,
+        1 => true,
+        _ => return Err(anyhow::anyhow!("invalid set variant {}", variant)),
+    };
+    let mut fingerprint = [0u8; 8];
+    reader.read_exact(&mut fingerprint)?;
+    Ok(SetHeader {
+        big,
+        kind,
+        fingerprint: u64::from_le_bytes(fingerprint),
+    })
 }
 
-pub fn build_series_from_bitmap(nodesets: Vec<RoaringBitmap>) -> Series {
+pub fn deserialize_set<R>(mut reader: R) -> anyhow::Result<EfficientSet>
+where
+    R: std::io::Read,
+{
+    let header = read_header(&mut reader)?;
+    let set = if header.big {
+        EfficientSet::BigSet(RoaringTreemap::deserialize_from(reader)?)
+    } else {
+        EfficientSet::SmallSet(RoaringBitmap::deserialize_from(reader)?)
+    };
+    Ok(set)
+}
+
+pub fn build_series_from_bitmap(nodesets: Vec<RoaringBitmap>, fingerprint: u64) -> Series {
     let mut arr = MutableBinaryArray::<i32>::new();
     for n in nodesets {
         let mut bytes = vec![];
-        serialize_set(&EfficientSet::SmallSet(n), &mut bytes).unwrap();
+        serialize_set(
+            &EfficientSet::SmallSet(n),
+            SetKind::Nodes,
+            fingerprint,
+            &mut bytes,
+        )
+        .unwrap();
         arr.push(Some(bytes))
     }
     let result: BinaryArray<i32> = arr.into();
     Series::try_from(("nodes", Box::new(result) as ArrayRef)).unwrap()
 }
 
-pub fn build_series_from_treemap(nodesets: Vec<RoaringTreemap>) -> Series {
+pub fn build_series_from_treemap(nodesets: Vec<RoaringTreemap>, fingerprint: u64) -> Series {
     let mut arr = MutableBinaryArray::<i32>::new();
     for n in nodesets {
         let mut bytes = vec![];
-        serialize_set(&EfficientSet::BigSet(n), &mut bytes).unwrap();
+        serialize_set(
+            &EfficientSet::BigSet(n),
+            SetKind::Nodes,
+            fingerprint,
+            &mut bytes,
+        )
+        .unwrap();
         arr.push(Some(bytes))
     }
     let result: BinaryArray<i32> = arr.into();
     Series::try_from(("nodes", Box::new(result) as ArrayRef)).unwrap()
 }
 
-pub fn build_series_from_sets(nodesets: Vec<EfficientSet>) -> Series {
+/// Serializes `nodesets` into a set column, `fingerprint` being the one of their graph (0 if unknown)
+pub fn build_series_from_sets(nodesets: Vec<EfficientSet>, fingerprint: u64) -> Series {
+    build_series_of_kind(nodesets, SetKind::Nodes, fingerprint)
+}
+
+pub fn build_series_of_kind(sets: Vec<EfficientSet>, kind: SetKind, fingerprint: u64) -> Series {
     let mut arr = MutableBinaryArray::<i32>::new();
-    for n in nodesets {
+    for n in sets {
         let mut bytes = vec![];
-        serialize_set(&n, &mut bytes).unwrap();
+        serialize_set(&n, kind, fingerprint, &mut bytes).unwrap();
         arr.push(Some(bytes))
     }
     let result: BinaryArray<i32> = arr.into();
     Series::try_from(("nodes", Box::new(result) as ArrayRef)).unwrap()
 }
 
-pub(crate) fn iter_roaring(series: &Series) -> impl Iterator<Item = EfficientSet> + '_ {
-    let chunks = series.binary().expect("series was not a list type");
-    let iter = chunks.into_iter();
-    iter.map(|row| {
-        let value = row.expect("row is null");
+fn binary_chunks(series: &Series) -> anyhow::Result<&BinaryChunked> {
+    let chunks = series.binary().map_err(|_| {
+        Error::InvalidSetColumn(format!(
+            "column {:?} has type {} instead of binary",
+            series.name(),
+            series.dtype()
+        ))
+    })?;
+    Ok(chunks)
+}
+
+pub(crate) fn iter_roaring(
+    series: &Series,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<EfficientSet>> + '_> {
+    let chunks = binary_chunks(series)?;
+    let iter = chunks.into_iter().enumerate();
+    Ok(iter.map(|(i, row)| -> anyhow::Result<EfficientSet> {
+        let value = row.ok_or_else(|| Error::InvalidSetColumn(format!("row {} is null", i)))?;
         let mut reader = std::io::Cursor::new(value);
-        deserialize_set(&mut reader).unwrap()
-    })
+        let set = deserialize_set(&mut reader).map_err(|e| {
+            Error::InvalidSetColumn(format!("row {} cannot be deserialized: {}", i, e))
+        })?;
+        Ok(set)
+    }))
+}
+
+/// Deserializes every row of a set column
+pub(crate) fn collect_sets(series: &Series) -> anyhow::Result<Vec<EfficientSet>> {
+    iter_roaring(series)?.collect()
+}
+
+/// Like `collect_sets`, but every set must be a `SmallSet`
+pub(crate) fn collect_bitmaps(series: &Series) -> anyhow::Result<Vec<RoaringBitmap>> {
+    iter_roaring(series)?
+        .map(|it| -> anyhow::Result<RoaringBitmap> { Ok(it?.try_into()?) })
+        .collect()
+}
+
+/// Combines the graph fingerprints of two sets (0 meaning unknown), failing if they differ
+pub(crate) fn combine_fingerprints(a: u64, b: u64) -> anyhow::Result<u64> {
+    match (a, b) {
+        (0, f) | (f, 0) => Ok(f),
+        (a, b) if a == b => Ok(a),
+        (a, b) => Err(Error::GraphMismatch(a, b).into()),
+    }
+}
+
+/// Combines the kinds of two sets (`Unknown` going with any kind), failing if they differ
+pub(crate) fn combine_kinds(a: SetKind, b: SetKind) -> anyhow::Result<SetKind> {
+    match (a, b) {
+        (SetKind::Unknown, k) | (k, SetKind::Unknown) => Ok(k),
+        (a, b) if a == b => Ok(a),
+        (a, b) => Err(Error::InvalidSetColumn(format!(
+            "cannot combine {:?} sets with {:?} sets",
+            a, b
+        ))
+        .into()),
+    }
+}
+
+/// Headers of the sets of a column (or of the lists of sets passed to aggregations)
+fn column_headers(series: &Series) -> anyhow::Result<Vec<SetHeader>> {
+    if matches!(series.dtype(), DataType::List(_)) {
+        let mut headers = vec![];
+        for group in series.list()?.into_iter().flatten() {
+            headers.extend(column_headers(&group)?);
+        }
+        return Ok(headers);
+    }
+    binary_chunks(series)?
+        .into_iter()
+        .flatten()
+        .map(|value| {
+            read_header(std::io::Cursor::new(value)).map_err(|e| {
+                Error::InvalidSetColumn(format!("cannot read the header of a set: {}", e)).into()
+            })
+        })
+        .collect()
+}
+
+/// The kind and the graph fingerprint shared by the sets of a column (`Unknown` and 0 if none
+/// carries one), which results computed from the column inherit
+pub(crate) fn column_meta(series: &Series) -> anyhow::Result<(SetKind, u64)> {
+    let mut kind = SetKind::Unknown;
+    let mut fingerprint = 0;
+    for header in column_headers(series)? {
+        kind = combine_kinds(kind, header.kind)?;
+        fingerprint = combine_fingerprints(fingerprint, header.fingerprint)?;
+    }
+    Ok((kind, fingerprint))
+}
+
+/// Fails if some set of the column is known to be of another kind than `kind`
+pub(crate) fn ensure_kind(series: &Series, kind: SetKind) -> anyhow::Result<()> {
+    for header in column_headers(series)? {
+        if header.kind != SetKind::Unknown && header.kind != kind {
+            return Err(Error::InvalidSetColumn(format!(
+                "column {:?} holds {:?} sets where {:?} sets are expected",
+                series.name(),
+                header.kind,
+                kind
+            ))
+            .into());
+        }
+    }
+    Ok(())
 }
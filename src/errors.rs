@@ -0,0 +1,66 @@
+use pyo3::{create_exception, exceptions::PyException, PyErr};
+use std::fmt;
+
+create_exception!(belinda, BelindaError, PyException);
+create_exception!(belinda, ClusteringFormatError, BelindaError);
+create_exception!(belinda, UnknownNodeError, BelindaError);
+create_exception!(belinda, InvalidSetColumnError, BelindaError);
+create_exception!(belinda, GraphMismatchError, BelindaError);
+
+/// Errors with a dedicated Python exception type
+#[derive(Debug)]
+pub enum Error {
+    /// The input clustering is malformed
+    ClusteringFormat(String),
+    /// A node id (in the original naming) that does not exist in the graph
+    UnknownNode(u64),
+    /// A column that was expected to hold serialized sets does not
+    InvalidSetColumn(String),
+    /// Sets built on a graph with a different fingerprint (found, expected)
+    GraphMismatch(u64, u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ClusteringFormat(msg) => write!(f, "malformed clustering: {}", msg),
+            Error::UnknownNode(node) => write!(f, "node {} does not exist in the graph", node),
+            Error::InvalidSetColumn(msg) => write!(f, "invalid set column: {}", msg),
+            Error::GraphMismatch(found, expected) => write!(
+                f,
+                "sets were built on a different graph (fingerprint {:016x}, expected {:016x})",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn convert(e: anyhow::Error, fallback: fn(String) -> PyErr) -> PyErr {
+    let msg = format!("{:#}", e);
+    for cause in e.chain() {
+        if let Some(err) = cause.downcast_ref::<Error>() {
+            return match err {
+                Error::ClusteringFormat(_) => ClusteringFormatError::new_err(msg),
+                Error::UnknownNode(_) => UnknownNodeError::new_err(msg),
+                Error::InvalidSetColumn(_) => InvalidSetColumnError::new_err(msg),
+                Error::GraphMismatch(..) => GraphMismatchError::new_err(msg),
+            };
+        }
+        if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+            return std::io::Error::new(err.kind(), msg).into();
+        }
+    }
+    fallback(msg)
+}
+
+/// Converts an error into the matching Python exception, keeping the context in the message
+pub fn to_py_err(e: anyhow::Error) -> PyErr {
+    convert(e, BelindaError::new_err)
+}
+
+/// Like `to_py_err`, but errors without a more specific type become `ClusteringFormatError`
+pub fn to_format_err(e: anyhow::Error) -> PyErr {
+    convert(e, ClusteringFormatError::new_err)
+}
@@ -1,11 +1,11 @@
+use ahash::{AHashMap, AHashSet};
+use anyhow::Context;
 use aocluster::{
     alg::{self, CCLabels},
-    aoc::rayon::{
-        self,
-        prelude::{
-            IndexedParallelIterator, IntoParallelIterator, ParallelBridge, ParallelIterator,
-        },
+    aoc::rayon::prelude::{
+        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
     },
+    base::{NameSet, Node},
     belinda::{
         EnrichedGraph,
     },
@@ -19,21 +19,25 @@ use pyo3::{
     prelude::*,
 };
 use roaring::{MultiOps, RoaringBitmap, RoaringTreemap};
-use std::{path::Path, sync::Arc, io::BufWriter, fs::File};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read},
+    path::Path,
+    sync::Arc,
+};
 
 use crate::{
-    df::{build_series_from_sets, iter_roaring, EfficientSet, VecEfficientSet},
+    df::{
+        build_series_of_kind, collect_bitmaps, collect_sets, column_meta, ensure_kind,
+        EfficientSet, SetKind, VecEfficientSet,
+    },
+    errors::{to_format_err, to_py_err, Error},
     ffi::{self, translate_df},
+    stats::{compute_stats, parse_stats, Stat},
+    threads,
 };
 
-#[pyfunction]
-pub fn set_nthreads(nthreads: usize) {
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(nthreads)
-        .build_global()
-        .unwrap();
-}
-
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[pyclass]
 pub enum SingletonMode {
@@ -42,72 +46,125 @@ pub enum SingletonMode {
     AsIs,
 }
 
-pub fn populate_clusdf(g: &Graph, df: &mut DataFrame) -> anyhow::Result<()> {
-    let g = &g.data.graph;
-    let bitmaps: Vec<RoaringBitmap> = iter_roaring(df.column("nodes")?)
-        .map(|n| n.try_into().unwrap())
-        .collect_vec();
-    let edges_bitmaps = g
-        .nodes
-        .iter()
-        .map(|n| RoaringBitmap::from_sorted_iter(n.edges.iter().map(|it| *it as u32)).unwrap())
-        .collect_vec();
-    let data: Vec<_> = bitmaps
-        .into_par_iter()
-        .map(|nodes| {
-            let mut m = 0u64;
-            let mut c = 0u64;
-            let mut mcd = (g.m() + 1) as u64;
-            for u in nodes.iter() {
-                let adj = &edges_bitmaps[u as usize];
-                let ic = adj.intersection_len(&nodes);
-                m += ic;
-                c += adj.len() as u64 - ic;
-                mcd = mcd.min(ic);
-            }
-            if mcd == (g.m() + 1) as u64 {
-                mcd = 0;
-            }
-            m /= 2;
-            (nodes.len(), m, c, mcd)
-        })
-        .collect();
-    let mut n_s = Vec::with_capacity(data.len());
-    let mut m_s = Vec::with_capacity(data.len());
-    let mut c_s = Vec::with_capacity(data.len());
-    let mut mcd_s = Vec::with_capacity(data.len());
-    for (n, m, c, mcd) in data {
-        n_s.push(n);
-        m_s.push(m);
-        c_s.push(c);
-        mcd_s.push(mcd);
-    }
-    df.with_column(Series::new("n", n_s))?;
-    df.with_column(Series::new("m", m_s))?;
-    df.with_column(Series::new("c", c_s))?;
-    df.with_column(Series::new("mcd", mcd_s))?;
-    Ok(())
+/// What to do with nodes of a clustering that do not exist in the graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[pyclass]
+pub enum UnknownNodePolicy {
+    /// Fail on the first unknown node
+    Error,
+    /// Silently drop unknown nodes
+    Drop,
+    /// Drop unknown nodes, also returning a data frame of `(label, unknown_node)` pairs
+    DropAndReport,
+    /// Drop at most one unknown (dummy) node per cluster, failing on the second
+    TolerateOneDummy,
 }
 
+/// Reads a clustering, also returning the unknown node report under `UnknownNodePolicy::DropAndReport`
 pub fn read_json<P: AsRef<Path>>(
     g: &Graph,
     filepath: P,
     mode: SingletonMode,
-) -> anyhow::Result<DataFrame> {
+    policy: UnknownNodePolicy,
+    stats: &[Stat],
+) -> anyhow::Result<(DataFrame, Option<DataFrame>)> {
     let mut file = std::fs::File::open(filepath)?;
-    let mut df = JsonLineReader::new(&mut file).finish()?;
+    let df = JsonLineReader::new(&mut file).finish()?;
+    node_lists_to_clusdf(g, df, mode, policy, stats)
+}
+
+pub fn read_parquet_clustering<P: AsRef<Path>>(
+    g: &Graph,
+    filepath: P,
+    mode: SingletonMode,
+    policy: UnknownNodePolicy,
+    stats: &[Stat],
+) -> anyhow::Result<(DataFrame, Option<DataFrame>)> {
+    let file = File::open(filepath)?;
+    let df = ParquetReader::new(file).finish()?;
+    node_lists_to_clusdf(g, df, mode, policy, stats)
+}
+
+/// Turns a data frame whose `nodes` column holds lists of original node ids into a clustering
+fn node_lists_to_clusdf(
+    g: &Graph,
+    mut df: DataFrame,
+    mode: SingletonMode,
+    policy: UnknownNodePolicy,
+    stats: &[Stat],
+) -> anyhow::Result<(DataFrame, Option<DataFrame>)> {
     df.with_column(
         df.column("nodes")?
             .cast(&DataType::List(Box::new(DataType::UInt32)))?,
     )?;
-    let mut nodes = node_list_to_bitmaps(g, df.column("nodes")?)?;
+    let (mut nodes, unknown) = node_list_to_bitmaps(g, df.column("nodes")?, policy)?;
+    let report = unknown_node_report(df.column("label")?, unknown, policy)?;
     nodes.rename("nodes");
     df.with_column(nodes)?;
     df = postprocess_singleton_mode(g, df, mode)?;
-    populate_clusdf(g, &mut df)?;
+    compute_stats(g, &mut df, stats)?;
+    Ok((df, report))
+}
+
+/// Builds the `(label, unknown_node)` data frame from the rows and nodes found by `node_list_to_bitmaps`
+fn unknown_node_report(
+    labels: &Series,
+    unknown: Vec<(u32, u32)>,
+    policy: UnknownNodePolicy,
+) -> anyhow::Result<Option<DataFrame>> {
+    if policy != UnknownNodePolicy::DropAndReport {
+        return Ok(None);
+    }
+    let (rows, nodes): (Vec<u32>, Vec<u32>) = unknown.into_iter().unzip();
+    let mut label = labels.take(&UInt32Chunked::from_vec("rows", rows))?;
+    label.rename("label");
+    Ok(Some(df!("label" => label, "unknown_node" => nodes)?))
+}
+
+fn translate_with_report(mut df: DataFrame, report: Option<DataFrame>) -> PyResult<PyObject> {
+    let df = translate_df(&mut df)?;
+    match report {
+        Some(mut report) => {
+            let report = translate_df(&mut report)?;
+            Ok(Python::with_gil(|py| (df, report).to_object(py)))
+        }
+        None => Ok(df),
+    }
+}
+
+/// Replaces the `nodes` column with lists of the original node ids
+fn with_node_lists(g: &Graph, df: &DataFrame) -> anyhow::Result<DataFrame> {
+    let mut df = df.clone();
+    let mut nodes = rust_nodeset_to_list(g, df.column("nodes")?)?;
+    nodes.rename("nodes");
+    df.with_column(nodes)?;
     Ok(df)
 }
 
+/// Writes the clustering as new-line delimited JSON, the inverse of `read_json`.
+/// All columns other than `nodes` are written as-is, but integer columns are read back as `Int64`.
+pub fn write_json<P: AsRef<Path>>(g: &Graph, df: &DataFrame, filepath: P) -> anyhow::Result<()> {
+    let mut df = with_node_lists(g, df)?;
+    let mut w = BufWriter::new(File::create(filepath)?);
+    JsonWriter::new(&mut w)
+        .with_json_format(JsonFormat::JsonLines)
+        .finish(&mut df)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Writes the clustering as Parquet with `nodes` stored as `list[u32]` of original node ids,
+/// so that the file does not depend on the internal ids of `g`
+pub fn write_parquet_clustering<P: AsRef<Path>>(
+    g: &Graph,
+    df: &DataFrame,
+    filepath: P,
+) -> anyhow::Result<()> {
+    let mut df = with_node_lists(g, df)?;
+    ParquetWriter::new(File::create(filepath)?).finish(&mut df)?;
+    Ok(())
+}
+
 /// Postprocesses a data frame with the singleton mode specified
 pub fn postprocess_singleton_mode(
     g: &Graph,
@@ -119,12 +176,13 @@ pub fn postprocess_singleton_mode(
     } else {
         1
     };
-    let mask: Series = iter_roaring(df.column("nodes")?)
-            .map(|it| it.len() >= lb)
-            .collect();
+    let mask: Series = collect_sets(df.column("nodes")?)?
+        .iter()
+        .map(|it| it.len() >= lb)
+        .collect();
     df = df.filter(mask.bool()?)?;
     if mode == SingletonMode::AutoPopulate {
-        let covered_nodes: RoaringBitmap = iter_roaring(df.column("nodes")?).collect_vec().union().try_into()?;
+        let covered_nodes: RoaringBitmap = collect_sets(df.column("nodes")?)?.union().try_into()?;
         // create two columns, a column of labels and a column of nodes
         let mut new_labels = vec![];
         let mut new_nodes: Vec<EfficientSet> = vec![];
@@ -139,22 +197,22 @@ pub fn postprocess_singleton_mode(
         let new_labels =
             Series::from_any_values_and_dtype("label", &new_labels, df.column("label")?.dtype())?;
         let k = new_labels.len();
-        let mut extend_df = df!("label" => new_labels, "nodes" => new_nodes.to_series())?;
-        df.get_column_names_owned().iter().for_each(|col: &String| {
+        let new_nodes = new_nodes.to_series(g.get_fingerprint());
+        let mut extend_df = df!("label" => new_labels, "nodes" => new_nodes)?;
+        for col in df.get_column_names_owned() {
             if col != "label" && col != "nodes" {
                 let mut null_filled = Vec::with_capacity(k);
                 for _i in 0..k {
                     null_filled.push(AnyValue::Null);
                 }
                 let s = Series::from_any_values_and_dtype(
-                    col,
+                    &col,
                     &null_filled,
-                    df.column(col).unwrap().dtype(),
-                )
-                .unwrap();
-                extend_df.with_column(s).unwrap();
+                    df.column(&col)?.dtype(),
+                )?;
+                extend_df.with_column(s)?;
             }
-        });
+        }
         df.extend(&extend_df)?;
     }
     Ok(df)
@@ -165,7 +223,9 @@ pub fn read_membership_series(
     nodes: &Series,
     cids: &Series,
     mode: SingletonMode,
-) -> anyhow::Result<DataFrame> {
+    policy: UnknownNodePolicy,
+    stats: &[Stat],
+) -> anyhow::Result<(DataFrame, Option<DataFrame>)> {
     let df = df!("nid" => nodes.cast(&DataType::UInt32)?, "cid" => cids)?;
     let mut df = df
         .lazy()
@@ -184,12 +244,13 @@ pub fn read_membership_series(
             .map(|f| f.map_or(false, |e| e.len() >= lb))
             .collect();
     df = df.filter(mask.bool()?)?;
-    let mut nodes = node_list_to_bitmaps(g, df.column("nid")?)?;
+    let (mut nodes, unknown) = node_list_to_bitmaps(g, df.column("nid")?, policy)?;
+    let report = unknown_node_report(df.column("cid")?, unknown, policy)?;
     nodes.rename("nodes");
     let mut df = df!("label" => df.column("cid")?, "nodes" => nodes)?;
     df = postprocess_singleton_mode(g, df, mode)?;
-    populate_clusdf(g, &mut df)?;
-    Ok(df)
+    compute_stats(g, &mut df, stats)?;
+    Ok((df, report))
 }
 
 pub fn read_membership_file(
@@ -197,8 +258,10 @@ pub fn read_membership_file(
     filepath: &str,
     sep: u8,
     mode: SingletonMode,
+    policy: UnknownNodePolicy,
     force_string_labels: bool,
-) -> anyhow::Result<DataFrame> {
+    stats: &[Stat],
+) -> anyhow::Result<(DataFrame, Option<DataFrame>)> {
     let df = CsvReader::from_path(filepath)?
         .has_header(false)
         .with_delimiter(sep)
@@ -210,76 +273,240 @@ pub fn read_membership_file(
         .finish()?;
     let nid = df.column("column_1")?;
     let cid = df.column("column_2")?;
-    read_membership_series(g, nid, cid, mode)
+    read_membership_series(g, nid, cid, mode, policy, stats).map_err(|e| {
+        // point at the first line mentioning the offending node
+        let line = match e.downcast_ref::<Error>() {
+            Some(Error::UnknownNode(node)) => nid.u32().ok().and_then(|nid| {
+                nid.into_iter()
+                    .position(|it| it.map(u64::from) == Some(*node))
+            }),
+            _ => None,
+        };
+        match line {
+            Some(i) => e.context(format!("{}:{}", filepath, i + 1)),
+            None => e.context(format!("cannot read {}", filepath)),
+        }
+    })
 }
 
 #[pyfunction(
     name = "read_membership",
     mode = "SingletonMode::AsIs",
     sep = "'\\t'",
-    force_string_labels = "false"
+    force_string_labels = "false",
+    policy = "UnknownNodePolicy::TolerateOneDummy",
+    stats = "None"
 )]
 pub fn py_read_membership_file(
+    py: Python,
     g: &Graph,
     filepath: &str,
     sep: char,
     mode: SingletonMode,
     force_string_labels: bool,
+    policy: UnknownNodePolicy,
+    stats: Option<Vec<&str>>,
 ) -> PyResult<PyObject> {
-    let mut df = read_membership_file(g, filepath, sep as u8, mode, force_string_labels).unwrap();
-    let translated = translate_df(&mut df)?;
-    Ok(translated)
+    let stats = parse_stats(stats)?;
+    let (df, report) = py
+        .allow_threads(|| {
+            read_membership_file(
+                g,
+                filepath,
+                sep as u8,
+                mode,
+                policy,
+                force_string_labels,
+                &stats,
+            )
+        })
+        .map_err(to_format_err)?;
+    translate_with_report(df, report)
 }
 
-#[pyfunction(name = "read_membership_series", mode = "SingletonMode::AsIs")]
+#[pyfunction(
+    name = "read_membership_series",
+    mode = "SingletonMode::AsIs",
+    policy = "UnknownNodePolicy::TolerateOneDummy",
+    stats = "None"
+)]
 pub fn py_from_memberships(
+    py: Python,
     g: &Graph,
     nodes: &PyAny,
     cids: &PyAny,
     mode: SingletonMode,
+    policy: UnknownNodePolicy,
+    stats: Option<Vec<&str>>,
 ) -> PyResult<PyObject> {
     let nodes = ffi::py_series_to_rust_series(nodes)?;
     let cids = ffi::py_series_to_rust_series(cids)?;
-    let mut df = read_membership_series(g, &nodes, &cids, mode).unwrap();
-    translate_df(&mut df)
+    let stats = parse_stats(stats)?;
+    let (df, report) = py
+        .allow_threads(|| read_membership_series(g, &nodes, &cids, mode, policy, &stats))
+        .map_err(to_format_err)?;
+    translate_with_report(df, report)
 }
 
-#[pyfunction(name = "read_json", mode = "SingletonMode::AsIs")]
-pub fn py_read_json(g: &Graph, filepath: &str, mode: SingletonMode) -> PyResult<PyObject> {
-    let mut df = read_json(g, filepath, mode).unwrap();
-    translate_df(&mut df)
+#[pyfunction(
+    name = "read_json",
+    mode = "SingletonMode::AsIs",
+    policy = "UnknownNodePolicy::TolerateOneDummy",
+    stats = "None"
+)]
+pub fn py_read_json(
+    py: Python,
+    g: &Graph,
+    filepath: &str,
+    mode: SingletonMode,
+    policy: UnknownNodePolicy,
+    stats: Option<Vec<&str>>,
+) -> PyResult<PyObject> {
+    let stats = parse_stats(stats)?;
+    let (df, report) = py
+        .allow_threads(|| read_json(g, filepath, mode, policy, &stats))
+        .with_context(|| format!("cannot read {}", filepath))
+        .map_err(to_format_err)?;
+    translate_with_report(df, report)
 }
 
-pub fn node_list_to_bitmaps(g: &Graph, list: &Series) -> anyhow::Result<Series> {
+/// Writes the clustering back in the membership format, one `node<sep>label`
+/// line per (node, cluster) pair. Clusters with a null label are skipped.
+pub fn write_membership<P: AsRef<Path>>(
+    g: &Graph,
+    labels: &Series,
+    nodes: &Series,
+    filepath: P,
+    sep: char,
+) -> anyhow::Result<()> {
+    let sets = g.bitmaps_of(nodes)?;
+    let g = &g.data.graph;
+    let labels = labels.cast(&DataType::Utf8)?;
+    let mut w = BufWriter::new(File::create(filepath)?);
+    for (ns, label) in sets.into_iter().zip(labels.utf8()?) {
+        let label = match label {
+            Some(label) => label,
+            None => continue,
+        };
+        for u in ns.iter() {
+            writeln!(w, "{}{}{}", g.name_set.rev[u as usize], sep, label)?;
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+#[pyfunction(name = "write_membership", sep = "'\\t'")]
+pub fn py_write_membership(
+    py: Python,
+    g: &Graph,
+    clus: &PyAny,
+    filepath: &str,
+    sep: char,
+) -> PyResult<()> {
+    let labels = ffi::py_series_to_rust_series(clus.call_method1("get_column", ("label",))?)?;
+    let nodes = ffi::py_series_to_rust_series(clus.call_method1("get_column", ("nodes",))?)?;
+    py.allow_threads(|| write_membership(g, &labels, &nodes, filepath, sep))
+        .map_err(to_py_err)
+}
+
+#[pyfunction(name = "write_json")]
+pub fn py_write_json(py: Python, g: &Graph, clus: &PyAny, filepath: &str) -> PyResult<()> {
+    let df = ffi::py_df_to_rust_df(clus)?;
+    py.allow_threads(|| write_json(g, &df, filepath))
+        .map_err(to_py_err)
+}
+
+#[pyfunction(
+    name = "read_parquet_clustering",
+    mode = "SingletonMode::AsIs",
+    policy = "UnknownNodePolicy::TolerateOneDummy",
+    stats = "None"
+)]
+pub fn py_read_parquet_clustering(
+    py: Python,
+    g: &Graph,
+    filepath: &str,
+    mode: SingletonMode,
+    policy: UnknownNodePolicy,
+    stats: Option<Vec<&str>>,
+) -> PyResult<PyObject> {
+    let stats = parse_stats(stats)?;
+    let (df, report) = py
+        .allow_threads(|| read_parquet_clustering(g, filepath, mode, policy, &stats))
+        .with_context(|| format!("cannot read {}", filepath))
+        .map_err(to_format_err)?;
+    translate_with_report(df, report)
+}
+
+#[pyfunction(name = "write_parquet_clustering")]
+pub fn py_write_parquet_clustering(
+    py: Python,
+    g: &Graph,
+    clus: &PyAny,
+    filepath: &str,
+) -> PyResult<()> {
+    let df = ffi::py_df_to_rust_df(clus)?;
+    py.allow_threads(|| write_parquet_clustering(g, &df, filepath))
+        .map_err(to_py_err)
+}
+
+/// Maps lists of original node ids onto sets of internal ids, handling unknown nodes per `policy`.
+/// Also returns the `(row, node)` pairs of dropped unknown nodes under `UnknownNodePolicy::DropAndReport`.
+pub fn node_list_to_bitmaps(
+    g: &Graph,
+    list: &Series,
+    policy: UnknownNodePolicy,
+) -> anyhow::Result<(Series, Vec<(u32, u32)>)> {
+    let fingerprint = g.get_fingerprint();
     let g = &g.data.graph;
     let as_list = list.list()?;
-    let sets: Vec<EfficientSet> = as_list
-        .par_iter()
-        .map(|e| {
-            e.map_or_else(
-                || RoaringBitmap::new().into(),
-                |series| {
-                    let mut seen_nonexistent = false;
-                    let mut bitmap = RoaringBitmap::new();
-                    series.u32().unwrap().into_iter().flatten().for_each(|x| {
-                        match g.retrieve(x as usize) {
-                            Some(internal_id) => {
-                                bitmap.insert(internal_id as u32);
-                            }
-                            None => {
-                                if seen_nonexistent {
-                                    panic!("Nonexistent node that is not singleton: {}", x);
+    let rows: Vec<(EfficientSet, Vec<u32>)> = threads::install(|| {
+        as_list
+            .par_iter()
+            .map(|e| {
+                e.map_or_else(
+                    || Ok((RoaringBitmap::new().into(), vec![])),
+                    |series| -> anyhow::Result<(EfficientSet, Vec<u32>)> {
+                        let mut unknown = vec![];
+                        let mut bitmap = RoaringBitmap::new();
+                        for x in series.u32()?.into_iter().flatten() {
+                            match g.retrieve(x as usize) {
+                                Some(internal_id) => {
+                                    // node sets are built as `SmallSet`
+                                    let internal_id = u32::try_from(internal_id).map_err(|_| {
+                                        anyhow::anyhow!("graphs with more than 2^32 nodes are not supported")
+                                    })?;
+                                    bitmap.insert(internal_id);
                                 }
-                                seen_nonexistent = true;
+                                None => match policy {
+                                    UnknownNodePolicy::Error => {
+                                        return Err(Error::UnknownNode(x as u64).into());
+                                    }
+                                    UnknownNodePolicy::TolerateOneDummy if !unknown.is_empty() => {
+                                        return Err(Error::UnknownNode(x as u64)).context(
+                                            "only one nonexistent (dummy) node is tolerated per cluster",
+                                        );
+                                    }
+                                    _ => unknown.push(x),
+                                },
                             }
                         }
-                    });
-                    bitmap.into()
-                },
-            )
-        })
-        .collect();
-    Ok(sets.to_series())
+                        Ok((bitmap.into(), unknown))
+                    },
+                )
+            })
+            .collect::<anyhow::Result<_>>()
+    })?;
+    let mut sets = Vec::with_capacity(rows.len());
+    let mut unknown = vec![];
+    for (i, (set, nodes)) in rows.into_iter().enumerate() {
+        sets.push(set);
+        if policy == UnknownNodePolicy::DropAndReport {
+            unknown.extend(nodes.into_iter().map(|x| (i as u32, x)));
+        }
+    }
+    Ok((sets.to_series(fingerprint), unknown))
 }
 
 #[pyclass]
@@ -287,43 +514,319 @@ pub fn node_list_to_bitmaps(g: &Graph, list: &Series) -> anyhow::Result<Series>
 pub struct Graph {
     data: Arc<EnrichedGraph>,
     cc: OnceCell<CCLabels>,
+    adj: OnceCell<Vec<RoaringBitmap>>,
+    fingerprint: OnceCell<u64>,
 }
 
-impl Graph {
-    pub fn get_cc_labels(&self) -> &CCLabels {
-        self.cc.get_or_init(|| alg::cc_labeling(&self.data.graph))
+/// Builds the underlying graph from edge series, assigning internal ids in order of first
+/// appearance just like `aocluster::base::Graph::parse_from_file`
+fn graph_from_edges(src: &Series, dst: &Series) -> anyhow::Result<aocluster::base::Graph<Node>> {
+    anyhow::ensure!(
+        src.len() == dst.len(),
+        "edge series have different lengths ({} vs. {})",
+        src.len(),
+        dst.len()
+    );
+    let src = src.cast(&DataType::UInt64)?;
+    let dst = dst.cast(&DataType::UInt64)?;
+    let mut name_set = NameSet::default();
+    let mut nodes: Vec<Node> = vec![];
+    for (u, v) in src.u64()?.into_iter().zip(dst.u64()?) {
+        let (u, v) = match (u, v) {
+            (Some(u), Some(v)) => (u as usize, v as usize),
+            _ => anyhow::bail!("edge series contain null values"),
+        };
+        let u = name_set.bi_onto(u);
+        let v = name_set.bi_onto(v);
+        while nodes.len() <= u.max(v) {
+            nodes.push(Node {
+                id: nodes.len(),
+                edges: vec![],
+            });
+        }
+        if u == v {
+            continue;
+        }
+        nodes[u].edges.push(v);
+        nodes[v].edges.push(u);
+    }
+    let mut m = 0;
+    for n in nodes.iter_mut() {
+        n.edges.sort_unstable();
+        n.edges.dedup();
+        m += n.edges.len();
     }
+    Ok(aocluster::base::Graph {
+        name_set,
+        nodes,
+        m_cache: m / 2,
+    })
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// One step of 64-bit FNV-1a, which unlike `std`'s hasher is stable across Rust versions
+fn fnv1a(h: u64, value: u64) -> u64 {
+    value
+        .to_le_bytes()
+        .iter()
+        .fold(h, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Leading bytes of a decompressed graph snapshot: a marker and the format version. Files
+/// without them hold a bare `aocluster` graph, like `resources/com-dblp.bincode.lz4`.
+const GRAPH_MAGIC: [u8; 4] = [0xB1, b'N', b'G', 1];
+
+/// On-disk snapshot of an `EnrichedGraph`, written by `Graph::save`
+#[derive(Serialize, Deserialize)]
+struct GraphSnapshot<G, A> {
+    graph: G,
+    acc_num_edges: A,
 }
 
-#[pymethods]
 impl Graph {
-    #[new]
-    fn new(filepath: &str) -> anyhow::Result<Self> {
-        let raw_data =
-            EnrichedGraph::from_graph(aocluster::base::Graph::parse_from_file(filepath)?);
-        Ok(Graph {
-            data: Arc::new(raw_data),
+    fn from_enriched(data: EnrichedGraph) -> Self {
+        Graph {
+            data: Arc::new(data),
             cc: OnceCell::new(),
+            adj: OnceCell::new(),
+            fingerprint: OnceCell::new(),
+        }
+    }
+
+    pub fn get_cc_labels(&self) -> &CCLabels {
+        self.cc
+            .get_or_init(|| threads::install(|| alg::cc_labeling(&self.data.graph)))
+    }
+
+    /// The neighbours of each node as a bitmap, built on first use
+    pub fn get_adjacency(&self) -> &[RoaringBitmap] {
+        self.adj.get_or_init(|| {
+            threads::install(|| {
+                self.data
+                    .graph
+                    .nodes
+                    .par_iter()
+                    .map(|n| {
+                        RoaringBitmap::from_sorted_iter(n.edges.iter().map(|it| *it as u32))
+                            .unwrap()
+                    })
+                    .collect()
+            })
         })
     }
 
-    fn write_edgelist(&self, filepath: &str) -> anyhow::Result<()> {
-        let g = &self.data.graph;
-        let mut w = BufWriter::new(File::create(filepath)?);
-        for u in &g.nodes {
-            for v in &u.edges {
-                if u.id < *v {
-                    let lhs = g.name_set.rev[u.id as usize];
-                    let rhs = g.name_set.rev[*v as usize];
-                    writeln!(w, "{}\t{}", lhs, rhs)?;
+    /// Stable hash of the node naming and the edges, i.e., of everything the internal ids in
+    /// set columns depend on. Never 0, which marks sets of an unknown graph.
+    pub fn get_fingerprint(&self) -> u64 {
+        *self.fingerprint.get_or_init(|| {
+            let g = &self.data.graph;
+            let mut h = fnv1a(FNV_OFFSET, g.n() as u64);
+            for (u, node) in g.nodes.iter().enumerate() {
+                h = fnv1a(h, g.name_set.rev[u] as u64);
+                h = fnv1a(h, node.edges.len() as u64);
+                for &v in &node.edges {
+                    h = fnv1a(h, v as u64);
                 }
             }
+            h.max(1)
+        })
+    }
+
+    /// Fails if `series` holds sets built on another graph, or edge sets
+    pub(crate) fn check_sets(&self, series: &Series) -> anyhow::Result<()> {
+        self.check_fingerprint(series)?;
+        ensure_kind(series, SetKind::Nodes)
+    }
+
+    fn check_fingerprint(&self, series: &Series) -> anyhow::Result<()> {
+        let found = column_meta(series)?.1;
+        if found != 0 && found != self.get_fingerprint() {
+            return Err(Error::GraphMismatch(found, self.get_fingerprint()).into());
         }
         Ok(())
     }
 
-    #[args(verbose = false)]
-    fn nodes(&self, clus: Option<&PyAny>, verbose: bool) -> PyResult<PyObject> {
+    /// Fails if one of `maxima` (the largest member of each set) is not a node of the graph.
+    /// Sets of an unknown graph (fingerprint 0) or built by hand may hold such ids.
+    fn check_bounds(&self, mut maxima: impl Iterator<Item = u64>) -> anyhow::Result<()> {
+        let n = self.data.graph.n() as u64;
+        match maxima.find(|&u| u >= n) {
+            Some(u) => Err(Error::InvalidSetColumn(format!(
+                "internal id {} is beyond the {} nodes of the graph",
+                u, n
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `collect_sets`, checking that the sets were built on this graph
+    pub(crate) fn sets_of(&self, series: &Series) -> anyhow::Result<Vec<EfficientSet>> {
+        let sets = self.unbounded_sets_of(series)?;
+        self.check_bounds(sets.iter().filter_map(EfficientSet::max))?;
+        Ok(sets)
+    }
+
+    /// Like `sets_of`, but keeps sets with ids beyond the graph, for `validate` to report them
+    fn unbounded_sets_of(&self, series: &Series) -> anyhow::Result<Vec<EfficientSet>> {
+        self.check_sets(series)?;
+        collect_sets(series)
+    }
+
+    /// Like `collect_bitmaps`, checking that the sets were built on this graph
+    pub(crate) fn bitmaps_of(&self, series: &Series) -> anyhow::Result<Vec<RoaringBitmap>> {
+        self.check_sets(series)?;
+        let bitmaps = collect_bitmaps(series)?;
+        self.check_bounds(bitmaps.iter().filter_map(|bm| bm.max().map(u64::from)))?;
+        Ok(bitmaps)
+    }
+
+    /// The original id of the node with internal id `u`
+    pub(crate) fn original_id(&self, u: u64) -> u64 {
+        self.data.graph.name_set.rev[u as usize] as u64
+    }
+
+    /// The internal id of the node with original id `v`, if it is in the graph
+    pub(crate) fn find_internal_id(&self, v: u32) -> Option<u32> {
+        self.data.graph.retrieve(v as usize).map(|u| u as u32)
+    }
+
+    /// The internal id of the node with original id `v`
+    fn internal_id(&self, v: u32) -> anyhow::Result<u32> {
+        let u = self
+            .find_internal_id(v)
+            .ok_or(Error::UnknownNode(v as u64))?;
+        Ok(u)
+    }
+
+    /// Internal ids of the (original) node ids in `nodes`, nulls staying null
+    fn internal_ids(&self, nodes: &Series) -> anyhow::Result<Vec<Option<u32>>> {
+        let nodes = nodes.cast(&DataType::UInt32)?;
+        let mut ids = vec![];
+        for v in nodes.u32()? {
+            ids.push(v.map(|v| self.internal_id(v)).transpose()?);
+        }
+        Ok(ids)
+    }
+
+    /// Endpoints (internal ids, `u < v`) of the edge numbered `e` by `edgeset`, `adj` being
+    /// `get_adjacency()`
+    fn edge_endpoints(&self, adj: &[RoaringBitmap], e: u64) -> Option<(u32, u32)> {
+        let acc = &self.data.acc_num_edges[..self.data.graph.n()];
+        let u = acc.partition_point(|&a| a <= e).checked_sub(1)?;
+        let neighbors = &adj[u];
+        let v = neighbors.select((neighbors.rank(u as u32) + e - acc[u]) as u32)?;
+        Some((u as u32, v))
+    }
+
+    /// The id `edgeset` gives to the edge between internal ids `u` and `v`, if there is one
+    fn edge_id(&self, adj: &[RoaringBitmap], u: u32, v: u32) -> Option<u64> {
+        let (u, v) = (u.min(v), u.max(v));
+        let neighbors = &adj[u as usize];
+        if u == v || !neighbors.contains(v) {
+            return None;
+        }
+        Some(self.data.acc_num_edges[u as usize] + edge_offset(neighbors, u, v))
+    }
+
+    /// The endpoints (original ids) of the edges of each set of an edge set column
+    fn edges_of_series(&self, series: &Series) -> anyhow::Result<Series> {
+        self.check_fingerprint(series)?;
+        ensure_kind(series, SetKind::Edges)?;
+        let rev = &self.data.graph.name_set.rev;
+        let sets = collect_sets(series)?;
+        // built here rather than by the workers, which would re-enter the `OnceCell`
+        let adj = self.get_adjacency();
+        let rows = threads::install(|| {
+            sets.into_par_iter()
+                .map(|set| -> anyhow::Result<Series> {
+                    let (mut us, mut vs) = (vec![], vec![]);
+                    for e in set.iter() {
+                        let (u, v) = self.edge_endpoints(adj, e).ok_or_else(|| {
+                            Error::InvalidSetColumn(format!(
+                                "edge {} does not exist in the graph",
+                                e
+                            ))
+                        })?;
+                        us.push(rev[u as usize] as u32);
+                        vs.push(rev[v as usize] as u32);
+                    }
+                    let fields = [Series::new("u", us), Series::new("v", vs)];
+                    Ok(StructChunked::new("edge", &fields)?.into_series())
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        })?;
+        Ok(Series::new("edges", rows))
+    }
+
+    /// The edge ids of the pairs of (original) node ids in `us` and `vs`, null for non-edges
+    fn edge_ids_series(&self, us: &Series, vs: &Series) -> anyhow::Result<Series> {
+        if us.len() != vs.len() {
+            return Err(anyhow::anyhow!(
+                "cannot pair up endpoint columns of lengths {} and {}",
+                us.len(),
+                vs.len()
+            ));
+        }
+        let us = us.cast(&DataType::UInt32)?;
+        let vs = vs.cast(&DataType::UInt32)?;
+        let adj = self.get_adjacency();
+        let mut ids = vec![];
+        for (u, v) in us.u32()?.into_iter().zip(vs.u32()?) {
+            let id = match (u, v) {
+                (Some(u), Some(v)) => self.edge_id(adj, self.internal_id(u)?, self.internal_id(v)?),
+                _ => None,
+            };
+            ids.push(id);
+        }
+        Ok(Series::new("edge_id", ids))
+    }
+
+    /// For each node of `query`, the labels of the clusters (`labels` and `nodes` columns) containing it
+    fn clusters_of_df(
+        &self,
+        labels: &Series,
+        nodes: &Series,
+        query: &Series,
+    ) -> anyhow::Result<DataFrame> {
+        let g = &self.data.graph;
+        let ids = self.internal_ids(query)?;
+        let wanted = ids.iter().flatten().copied().collect::<RoaringBitmap>();
+        let mut rows: AHashMap<u32, Vec<u32>> = AHashMap::default();
+        for (i, ns) in self.bitmaps_of(nodes)?.into_iter().enumerate() {
+            for u in (&ns & &wanted).iter() {
+                rows.entry(u).or_default().push(i as u32);
+            }
+        }
+        let mut found = vec![];
+        for u in &ids {
+            found.push(match u {
+                Some(u) => {
+                    let rows = rows.get(u).cloned().unwrap_or_default();
+                    Some(labels.take(&UInt32Chunked::from_vec("rows", rows))?)
+                }
+                None => None,
+            });
+        }
+        let mut found: ListChunked = found.into_iter().collect();
+        found.rename("labels");
+        let node = ids
+            .iter()
+            .map(|u| u.map(|u| g.name_set.rev[u as usize] as u32))
+            .collect_vec();
+        Ok(df!(
+            "node" => node,
+            "labels" => found.into_series(),
+        )?)
+    }
+
+    /// The table behind `Graph.nodes`, `clus` being the `label` and `nodes` columns of a clustering
+    fn nodes_df(
+        &self,
+        clus: Option<(&Series, &Series)>,
+        verbose: bool,
+    ) -> anyhow::Result<DataFrame> {
         let g = &self.data.graph;
         let nodes = (0..self.n())
             .map(|it| g.name_set.rev[it as usize] as u32)
@@ -334,8 +837,7 @@ impl Graph {
         let mut df = df!(
             "node" => nodes,
             "degree" => degrees,
-        )
-        .unwrap();
+        )?;
         if verbose {
             let adj = (0..self.n())
                 .map(|it| {
@@ -346,28 +848,21 @@ impl Graph {
                         .collect::<Series>()
                 })
                 .collect_vec();
-            df.with_column(Series::new("adj", adj)).unwrap();
+            df.with_column(Series::new("adj", adj))?;
         }
-        if let Some(clus) = clus {
-            let label =
-                ffi::py_series_to_rust_series(clus.call_method1("get_column", ("label",))?)?;
+        if let Some((label, nodes)) = clus {
             let label_t = label.dtype();
             let mut labels_u32: Vec<Vec<Option<u32>>> = vec![vec![]; self.n() as usize];
             let mut labels_str: Vec<Vec<String>> = vec![vec![]; self.n() as usize];
-            let nodes =
-                ffi::py_series_to_rust_series(clus.call_method1("get_column", ("nodes",))?)?;
             if label_t != &DataType::Utf8 {
-                for (ns, label) in
-                    iter_roaring(&nodes).zip(label.cast(&DataType::UInt32).unwrap().u32().unwrap())
-                {
-                    let ns: RoaringBitmap = ns.try_into().unwrap();
+                let label = label.cast(&DataType::UInt32)?;
+                for (ns, label) in self.bitmaps_of(nodes)?.into_iter().zip(label.u32()?) {
                     for node in ns.into_iter() {
                         labels_u32[node as usize].push(label);
                     }
                 }
             } else {
-                for (ns, label) in iter_roaring(&nodes).zip(label.utf8().unwrap()) {
-                    let ns: RoaringBitmap = ns.try_into().unwrap();
+                for (ns, label) in self.bitmaps_of(nodes)?.into_iter().zip(label.utf8()?) {
                     for node in ns.into_iter() {
                         labels_str[node as usize].push(label.unwrap_or_default().to_string());
                     }
@@ -382,34 +877,191 @@ impl Graph {
                 .map(|it| it.into_iter().collect::<Series>())
                 .collect_vec();
             if label_t != &DataType::Utf8 {
-                df.with_column(Series::new("labels", labels_u32)).unwrap();
+                df.with_column(Series::new("labels", labels_u32))?;
             } else {
-                df.with_column(Series::new("labels", labels_str)).unwrap();
+                df.with_column(Series::new("labels", labels_str))?;
             }
         }
+        Ok(df)
+    }
+}
+
+#[pymethods]
+impl Graph {
+    #[new]
+    fn new(py: Python, filepath: &str) -> PyResult<Self> {
+        py.allow_threads(|| {
+            threads::install(|| {
+                let raw_data = aocluster::base::Graph::parse_from_file(filepath)
+                    .with_context(|| format!("cannot read graph from {}", filepath))?;
+                Ok(Graph::from_enriched(EnrichedGraph::from_graph(raw_data)))
+            })
+        })
+        .map_err(to_py_err)
+    }
+
+    /// Builds a graph from two series of original node ids, each pair `(src[i], dst[i])` being an edge
+    #[staticmethod]
+    fn from_edges(py: Python, src: &PyAny, dst: &PyAny) -> PyResult<Self> {
+        let src = ffi::py_series_to_rust_series(src)?;
+        let dst = ffi::py_series_to_rust_series(dst)?;
+        py.allow_threads(|| {
+            let graph = graph_from_edges(&src, &dst)?;
+            let raw_data = threads::install(|| EnrichedGraph::from_graph(graph));
+            Ok(Graph::from_enriched(raw_data))
+        })
+        .map_err(to_py_err)
+    }
+
+    /// Loads a graph previously written by `save`, or a lz4-compressed bare `aocluster` graph
+    #[staticmethod]
+    fn load(py: Python, filepath: &str) -> PyResult<Self> {
+        let load = || -> anyhow::Result<Self> {
+            let mut decoder = lz4::Decoder::new(BufReader::new(File::open(filepath)?))?;
+            let mut magic = [0u8; 4];
+            decoder.read_exact(&mut magic)?;
+            let data = if magic == GRAPH_MAGIC {
+                let snapshot: GraphSnapshot<_, _> = bincode::deserialize_from(decoder)?;
+                EnrichedGraph {
+                    graph: snapshot.graph,
+                    acc_num_edges: snapshot.acc_num_edges,
+                }
+            } else if magic[..3] == GRAPH_MAGIC[..3] {
+                anyhow::bail!(
+                    "graph snapshot version {} is newer than the supported version {}",
+                    magic[3],
+                    GRAPH_MAGIC[3]
+                );
+            } else {
+                let graph: aocluster::base::Graph<Node> =
+                    bincode::deserialize_from(magic.as_slice().chain(decoder))?;
+                threads::install(|| EnrichedGraph::from_graph(graph))
+            };
+            Ok(Graph::from_enriched(data))
+        };
+        py.allow_threads(load)
+            .with_context(|| format!("cannot load graph from {}", filepath))
+            .map_err(to_py_err)
+    }
+
+    /// Saves the graph (including the node name mapping) as a lz4-compressed binary snapshot
+    fn save(&self, py: Python, filepath: &str) -> PyResult<()> {
+        let save = || -> anyhow::Result<()> {
+            let snapshot = GraphSnapshot {
+                graph: &self.data.graph,
+                acc_num_edges: &self.data.acc_num_edges,
+            };
+            let mut encoder =
+                lz4::EncoderBuilder::new().build(BufWriter::new(File::create(filepath)?))?;
+            encoder.write_all(&GRAPH_MAGIC)?;
+            bincode::serialize_into(&mut encoder, &snapshot)?;
+            let (mut w, result) = encoder.finish();
+            result?;
+            w.flush()?;
+            Ok(())
+        };
+        py.allow_threads(save).map_err(to_py_err)
+    }
+
+    fn write_edgelist(&self, py: Python, filepath: &str) -> PyResult<()> {
+        let write = || -> anyhow::Result<()> {
+            let g = &self.data.graph;
+            let mut w = BufWriter::new(File::create(filepath)?);
+            for u in &g.nodes {
+                for v in &u.edges {
+                    if u.id < *v {
+                        let lhs = g.name_set.rev[u.id as usize];
+                        let rhs = g.name_set.rev[*v as usize];
+                        writeln!(w, "{}\t{}", lhs, rhs)?;
+                    }
+                }
+            }
+            w.flush()?;
+            Ok(())
+        };
+        py.allow_threads(write).map_err(to_py_err)
+    }
+
+    #[args(verbose = false)]
+    fn nodes(&self, py: Python, clus: Option<&PyAny>, verbose: bool) -> PyResult<PyObject> {
+        let clus = match clus {
+            Some(clus) => Some((
+                ffi::py_series_to_rust_series(clus.call_method1("get_column", ("label",))?)?,
+                ffi::py_series_to_rust_series(clus.call_method1("get_column", ("nodes",))?)?,
+            )),
+            None => None,
+        };
+        let mut df = py
+            .allow_threads(|| self.nodes_df(clus.as_ref().map(|(l, n)| (l, n)), verbose))
+            .map_err(to_py_err)?;
+        translate_df(&mut df)
+    }
+
+    /// The labels of the clusters containing each of `node_ids`
+    fn clusters_of(&self, py: Python, clus: &PyAny, node_ids: &PyAny) -> PyResult<PyObject> {
+        let labels = ffi::py_series_to_rust_series(clus.call_method1("get_column", ("label",))?)?;
+        let nodes = ffi::py_series_to_rust_series(clus.call_method1("get_column", ("nodes",))?)?;
+        let query = ffi::py_series_to_rust_series(node_ids)?;
+        let mut df = py
+            .allow_threads(|| self.clusters_of_df(&labels, &nodes, &query))
+            .map_err(to_py_err)?;
         translate_df(&mut df)
     }
 
-    fn covered_edges(&self, n: &PyAny) -> PyResult<PyObject> {
+    fn covered_edges(&self, py: Python, n: &PyAny) -> PyResult<PyObject> {
         let series = ffi::py_series_to_rust_series(n)?;
-        let g = &self.data;
-        let nodesets = iter_roaring(&series)
-            .map(|it| it.try_into().unwrap())
-            .map(|it| edgeset(g, &it))
-            .map(EfficientSet::BigSet)
-            .collect::<Vec<_>>();
-        ffi::rust_series_to_py_series(&build_series_from_sets(nodesets))
+        let out = py
+            .allow_threads(|| -> anyhow::Result<Series> {
+                let adj = self.get_adjacency();
+                let edgesets = self
+                    .bitmaps_of(&series)?
+                    .iter()
+                    .map(|it| EfficientSet::BigSet(edgeset(self, adj, it)))
+                    .collect::<Vec<_>>();
+                Ok(build_series_of_kind(
+                    edgesets,
+                    SetKind::Edges,
+                    self.get_fingerprint(),
+                ))
+            })
+            .map_err(to_py_err)?;
+        ffi::rust_series_to_py_series(&out)
+    }
+
+    /// The endpoints of the edges in each set of a `covered_edges` column
+    fn edges_of(&self, py: Python, edges: &PyAny) -> PyResult<PyObject> {
+        let series = ffi::py_series_to_rust_series(edges)?;
+        let out = py
+            .allow_threads(|| self.edges_of_series(&series))
+            .map_err(to_py_err)?;
+        ffi::rust_series_to_py_series(&out)
+    }
+
+    /// The ids `covered_edges` gives to the edges between `u` and `v`, null for non-edges
+    fn edge_ids(&self, py: Python, u: &PyAny, v: &PyAny) -> PyResult<PyObject> {
+        let us = ffi::py_series_to_rust_series(u)?;
+        let vs = ffi::py_series_to_rust_series(v)?;
+        let out = py
+            .allow_threads(|| self.edge_ids_series(&us, &vs))
+            .map_err(to_py_err)?;
+        ffi::rust_series_to_py_series(&out)
     }
 
-    fn covered_edges_count(&self, n: &PyAny) -> PyResult<u64> {
+    fn covered_edges_count(&self, py: Python, n: &PyAny) -> PyResult<u64> {
         let series = ffi::py_series_to_rust_series(n)?;
-        let g = &self.data;
-        let edgesets = iter_roaring(&series)
-            .map(|it| it.try_into().unwrap())
-            .par_bridge()
-            .map(|it| edgeset(g, &it))
-            .collect::<Vec<_>>();
-        Ok(edgesets.union().len() as u64)
+        py.allow_threads(|| {
+            let bitmaps = self.bitmaps_of(&series)?;
+            // built here rather than by the workers, which would re-enter the `OnceCell`
+            let adj = self.get_adjacency();
+            let edgesets = threads::install(|| {
+                bitmaps
+                    .into_par_iter()
+                    .map(|it| edgeset(self, adj, &it))
+                    .collect::<Vec<_>>()
+            });
+            Ok(edgesets.union().len() as u64)
+        })
+        .map_err(to_py_err)
     }
 
     #[getter]
@@ -418,10 +1070,16 @@ impl Graph {
     }
 
     #[getter]
-    fn m(&self) -> u64 {
+    pub fn m(&self) -> u64 {
         self.data.graph.m() as u64
     }
 
+    /// Stable hash of the graph, attached to the set columns built on it
+    #[getter]
+    fn fingerprint(&self, py: Python) -> u64 {
+        py.allow_threads(|| self.get_fingerprint())
+    }
+
     fn __str__(&self) -> PyResult<String> {
         Ok(format!(
             "Graph(n={}, m={})",
@@ -430,17 +1088,26 @@ impl Graph {
         ))
     }
 
-    fn num_components(&self) -> u32 {
-        self.get_cc_labels().num_nodes.len() as u32
+    /// Frees the adjacency bitmaps and connected components computed so far. They are rebuilt
+    /// when needed again.
+    fn drop_caches(&mut self) {
+        self.adj = OnceCell::new();
+        self.cc = OnceCell::new();
     }
 
-    fn largest_component(&self) -> u32 {
-        self.get_cc_labels()
-            .num_nodes
-            .iter()
-            .max()
-            .copied()
-            .unwrap() as u32
+    fn num_components(&self, py: Python) -> u32 {
+        py.allow_threads(|| self.get_cc_labels().num_nodes.len() as u32)
+    }
+
+    fn largest_component(&self, py: Python) -> u32 {
+        py.allow_threads(|| {
+            self.get_cc_labels()
+                .num_nodes
+                .iter()
+                .max()
+                .copied()
+                .unwrap_or(0) as u32
+        })
     }
 }
 
@@ -448,30 +1115,140 @@ pub fn rust_label_cc(g: &Graph, series: &Series) -> anyhow::Result<Series> {
     let labels = &g.get_cc_labels().labels;
     let g = &g.data.graph;
     let mut ans = vec![];
-    for u in series.u32().into_iter() {
-        for v in u {
-            ans.push(v.map(|v| labels[g.retrieve(v as usize).unwrap() as usize]));
-        }
+    for v in series.u32()? {
+        ans.push(match v {
+            Some(v) => {
+                let u = g.retrieve(v as usize).ok_or(Error::UnknownNode(v as u64))?;
+                Some(labels[u as usize])
+            }
+            None => None,
+        });
     }
-    Ok(Series::new("cc", ans))
+    Ok(Series::new("cc", ans).cast(&DataType::UInt32)?)
 }
 
 pub fn rust_label_cc_size(g: &Graph, series: &Series) -> anyhow::Result<Series> {
     let num_nodes = &g.get_cc_labels().num_nodes;
     let mut ans = vec![];
-    for u in series.u32().into_iter() {
-        for v in u {
-            ans.push(v.map(|v| num_nodes[v as usize]));
+    for v in series.u32()? {
+        ans.push(match v {
+            Some(v) => Some(
+                *num_nodes
+                    .get(v as usize)
+                    .ok_or_else(|| anyhow::anyhow!("{} is not a component label", v))?,
+            ),
+            None => None,
+        });
+    }
+    Ok(Series::new("cc_size", ans).cast(&DataType::UInt32)?)
+}
+
+/// Checks a clustering for problems that would otherwise surface as panics or wrong statistics,
+/// returning one row per issue found
+pub fn validate(g: &Graph, labels: &Series, nodes: &Series) -> anyhow::Result<DataFrame> {
+    let n = g.n();
+    let sets = g.unbounded_sets_of(nodes)?;
+    let g = &g.data.graph;
+    let labels = labels.cast(&DataType::Utf8)?;
+    let labels = labels.utf8()?;
+    let mut issue: Vec<&str> = vec![];
+    let mut label_s: Vec<Option<&str>> = vec![];
+    let mut node_s: Vec<Option<u32>> = vec![];
+    let mut detail: Vec<Option<String>> = vec![];
+    let mut label_counts: AHashMap<&str, usize> = AHashMap::new();
+    for label in labels.into_iter().flatten() {
+        *label_counts.entry(label).or_default() += 1;
+    }
+    let mut reported_labels: AHashSet<&str> = AHashSet::new();
+    let mut covered = RoaringBitmap::new();
+    let mut overlapping = RoaringBitmap::new();
+    let mut bitmaps = Vec::with_capacity(sets.len());
+    for (set, label) in sets.iter().zip(labels) {
+        match label {
+            None => {
+                issue.push("null_label");
+                label_s.push(None);
+                node_s.push(None);
+                detail.push(None);
+            }
+            Some(label) if label_counts[label] > 1 && reported_labels.insert(label) => {
+                issue.push("duplicate_label");
+                label_s.push(Some(label));
+                node_s.push(None);
+                detail.push(Some(format!(
+                    "{} clusters share this label",
+                    label_counts[label]
+                )));
+            }
+            _ => {}
+        }
+        if set.len() == 0 {
+            issue.push("empty_cluster");
+            label_s.push(label);
+            node_s.push(None);
+            detail.push(None);
+        }
+        if let EfficientSet::BigSet(_) = set {
+            issue.push("unexpected_bigset");
+            label_s.push(label);
+            node_s.push(None);
+            detail.push(Some("node sets are usually SmallSet".to_string()));
+        }
+        let mut bitmap = set.low_bits().into_owned();
+        let unknown = set.len() - bitmap.range_cardinality(..n);
+        if unknown > 0 {
+            issue.push("unknown_node");
+            label_s.push(label);
+            node_s.push(None);
+            detail.push(Some(format!("{} internal ids beyond the graph", unknown)));
+            // only reported here, as they have no original id to report them by
+            bitmap.remove_range(n..);
+        }
+        overlapping |= &covered & &bitmap;
+        covered |= &bitmap;
+        bitmaps.push(bitmap);
+    }
+    if !overlapping.is_empty() {
+        for (bitmap, label) in bitmaps.iter().zip(labels) {
+            for u in (bitmap & &overlapping).iter() {
+                issue.push("overlapping_node");
+                label_s.push(label);
+                node_s.push(Some(g.name_set.rev[u as usize] as u32));
+                detail.push(None);
+            }
         }
     }
-    Ok(Series::new("cc_size", ans))
+    for u in 0..n {
+        if !covered.contains(u) {
+            issue.push("uncovered_node");
+            label_s.push(None);
+            node_s.push(Some(g.name_set.rev[u as usize] as u32));
+            detail.push(None);
+        }
+    }
+    Ok(df!(
+        "issue" => issue,
+        "label" => label_s,
+        "node" => node_s,
+        "detail" => detail,
+    )?)
+}
+
+#[pyfunction(name = "validate")]
+pub fn py_validate(py: Python, g: &Graph, clus: &PyAny) -> PyResult<PyObject> {
+    let labels = ffi::py_series_to_rust_series(clus.call_method1("get_column", ("label",))?)?;
+    let nodes = ffi::py_series_to_rust_series(clus.call_method1("get_column", ("nodes",))?)?;
+    let mut df = py
+        .allow_threads(|| validate(g, &labels, &nodes))
+        .map_err(to_py_err)?;
+    translate_df(&mut df)
 }
 
 pub fn rust_nodeset_to_list(g: &Graph, series: &Series) -> anyhow::Result<Series> {
     let mut ans = vec![];
+    let sets = g.bitmaps_of(series)?;
     let g = &g.data.graph;
-    for bm in iter_roaring(series) {
-        let bm: RoaringBitmap = bm.try_into()?;
+    for bm in sets {
         let s = bm
             .iter()
             .map(|it| g.name_set.rev[it as usize] as u32)
@@ -481,71 +1258,120 @@ pub fn rust_nodeset_to_list(g: &Graph, series: &Series) -> anyhow::Result<Series
     Ok(Series::new("nodes_list", ans))
 }
 
-pub fn rust_popcnt(series: &Series) -> Series {
-    iter_roaring(series)
+pub fn rust_popcnt(series: &Series) -> anyhow::Result<Series> {
+    Ok(collect_sets(series)?
+        .iter()
         .map(|bitmap| bitmap.len() as u32)
-        .collect()
+        .collect())
 }
 
-pub fn rust_bitmap_union(series: &Series) -> Series {
-    let s = iter_roaring(series).collect::<Vec<EfficientSet>>();
-    build_series_from_sets(vec![s.union()])
+/// Reduces a set column to a single set or, given the list of sets of each group (as passed by
+/// `groupby().agg()`), each group to one set
+fn aggregate_sets(
+    series: &Series,
+    reduce: fn(Vec<EfficientSet>) -> EfficientSet,
+) -> anyhow::Result<Series> {
+    let (kind, fingerprint) = column_meta(series)?;
+    if !matches!(series.dtype(), DataType::List(_)) {
+        let s = collect_sets(series)?;
+        return Ok(build_series_of_kind(vec![reduce(s)], kind, fingerprint));
+    }
+    let groups = series.list()?.into_iter().collect_vec();
+    let sets = threads::install(|| {
+        groups
+            .into_par_iter()
+            .map(|group| -> anyhow::Result<EfficientSet> {
+                let s = match group {
+                    Some(group) => collect_sets(&group)?,
+                    None => vec![],
+                };
+                Ok(reduce(s))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+    Ok(build_series_of_kind(sets, kind, fingerprint))
 }
 
-fn edgeset(g: &EnrichedGraph, bm: &RoaringBitmap) -> RoaringTreemap {
-    let graph = &g.graph;
-    let acc = &g.acc_num_edges;
+pub fn rust_bitmap_union(series: &Series) -> anyhow::Result<Series> {
+    aggregate_sets(series, VecEfficientSet::union)
+}
+
+pub fn rust_bitmap_intersection(series: &Series) -> anyhow::Result<Series> {
+    aggregate_sets(series, VecEfficientSet::intersection)
+}
+
+/// Position of the neighbour `v > u` among the neighbours of `u` greater than `u`
+fn edge_offset(neighbors: &RoaringBitmap, u: u32, v: u32) -> u64 {
+    neighbors.rank(v) - neighbors.rank(u) - 1
+}
+
+/// The edges between nodes of `bm`. The edges from `u` to its neighbours `v > u` are numbered
+/// from `acc_num_edges[u]` on, by increasing `v`. `adj` is `g.get_adjacency()`, which must not
+/// be called from parallel workers.
+fn edgeset(g: &Graph, adj: &[RoaringBitmap], bm: &RoaringBitmap) -> RoaringTreemap {
+    let acc = &g.data.acc_num_edges;
     let tm = RoaringTreemap::from_sorted_iter(bm.iter().flat_map(|u| {
-        let edges = &graph.nodes[u as usize].edges;
+        let neighbors = &adj[u as usize];
         let shift = acc[u as usize];
-        edges
-            .iter()
-            .filter(move |e| u < **e as u32)
-            .enumerate()
-            .filter_map(move |(offset, &v)| {
-                if bm.contains(v as u32) {
-                    Some(shift + offset as u64)
-                } else {
-                    None
-                }
-            })
+        (neighbors & bm)
+            .into_iter()
+            .filter(move |&v| u < v)
+            .map(move |v| shift + edge_offset(neighbors, u, v))
     }))
     .unwrap();
     tm
 }
 
 #[pyfunction(name = "popcnt")]
-pub fn py_popcnt(series: &PyAny) -> PyResult<PyObject> {
+pub fn py_popcnt(py: Python, series: &PyAny) -> PyResult<PyObject> {
     let series = ffi::py_series_to_rust_series(series)?;
-    let out = rust_popcnt(&series);
+    let out = py
+        .allow_threads(|| rust_popcnt(&series))
+        .map_err(to_py_err)?;
     ffi::rust_series_to_py_series(&out)
 }
 
 #[pyfunction(name = "union")]
-pub fn py_bitmap_union(series: &PyAny) -> PyResult<PyObject> {
+pub fn py_bitmap_union(py: Python, series: &PyAny) -> PyResult<PyObject> {
     let series = ffi::py_series_to_rust_series(series)?;
-    let out = rust_bitmap_union(&series);
+    let out = py
+        .allow_threads(|| rust_bitmap_union(&series))
+        .map_err(to_py_err)?;
+    ffi::rust_series_to_py_series(&out)
+}
+
+#[pyfunction(name = "intersection_all")]
+pub fn py_bitmap_intersection(py: Python, series: &PyAny) -> PyResult<PyObject> {
+    let series = ffi::py_series_to_rust_series(series)?;
+    let out = py
+        .allow_threads(|| rust_bitmap_intersection(&series))
+        .map_err(to_py_err)?;
     ffi::rust_series_to_py_series(&out)
 }
 
 #[pyfunction(name = "cc_labels")]
-pub fn py_label_cc(g: &Graph, series: &PyAny) -> PyResult<PyObject> {
+pub fn py_label_cc(py: Python, g: &Graph, series: &PyAny) -> PyResult<PyObject> {
     let series = ffi::py_series_to_rust_series(series)?;
-    let out = rust_label_cc(g, &series).unwrap();
+    let out = py
+        .allow_threads(|| rust_label_cc(g, &series))
+        .map_err(to_py_err)?;
     ffi::rust_series_to_py_series(&out)
 }
 
 #[pyfunction(name = "cc_size")]
-pub fn py_label_cc_size(g: &Graph, series: &PyAny) -> PyResult<PyObject> {
+pub fn py_label_cc_size(py: Python, g: &Graph, series: &PyAny) -> PyResult<PyObject> {
     let series = ffi::py_series_to_rust_series(series)?;
-    let out = rust_label_cc_size(g, &series).unwrap();
+    let out = py
+        .allow_threads(|| rust_label_cc_size(g, &series))
+        .map_err(to_py_err)?;
     ffi::rust_series_to_py_series(&out)
 }
 
 #[pyfunction(name = "nodeset_to_list")]
-pub fn py_nodeset_to_list(g: &Graph, series: &PyAny) -> PyResult<PyObject> {
+pub fn py_nodeset_to_list(py: Python, g: &Graph, series: &PyAny) -> PyResult<PyObject> {
     let series = ffi::py_series_to_rust_series(series)?;
-    let out = rust_nodeset_to_list(g, &series).unwrap();
+    let out = py
+        .allow_threads(|| rust_nodeset_to_list(g, &series))
+        .map_err(to_py_err)?;
     ffi::rust_series_to_py_series(&out)
 }
-
@@ -24,8 +24,10 @@ fn array_to_rust(arrow_array: &PyAny) -> PyResult<ArrayRef> {
     )?;
 
     unsafe {
-        let field = ffi::import_field_from_c(schema.as_ref()).unwrap();
-        let array = ffi::import_array_from_c(*array, field.data_type).unwrap();
+        let field = ffi::import_field_from_c(schema.as_ref())
+            .map_err(|e| PyValueError::new_err(format!("{}", e)))?;
+        let array = ffi::import_array_from_c(*array, field.data_type)
+            .map_err(|e| PyValueError::new_err(format!("{}", e)))?;
         Ok(array)
     }
 }
@@ -80,16 +82,14 @@ pub fn translate_df(df: &mut DataFrame) -> PyResult<PyObject> {
     let gil = Python::acquire_gil();
     let py = gil.python();
     let pypolars = py.import("polars")?;
-    let py_series_obj: Vec<_> = py_series
+    let py_series_obj = py_series
         .into_iter()
         .map(|it| {
             pypolars
-                .getattr("Series")
-                .unwrap()
+                .getattr("Series")?
                 .call_method1("_from_arrow", (it.name, it.data))
-                .unwrap()
         })
-        .collect();
+        .collect::<PyResult<Vec<_>>>()?;
     let remapped_df = pypolars.getattr("DataFrame")?.call1((py_series_obj,))?;
     Ok(remapped_df.to_object(py))
 }
@@ -109,6 +109,16 @@ pub fn py_series_to_rust_series(series: &PyAny) -> PyResult<Series> {
     Series::try_from((name.as_str(), array)).map_err(|e| PyValueError::new_err(format!("{}", e)))
 }
 
+pub fn py_df_to_rust_df(df: &PyAny) -> PyResult<DataFrame> {
+    let columns = df
+        .call_method0("get_columns")?
+        .extract::<Vec<&PyAny>>()?
+        .into_iter()
+        .map(py_series_to_rust_series)
+        .collect::<PyResult<Vec<Series>>>()?;
+    DataFrame::new(columns).map_err(|e| PyValueError::new_err(format!("{}", e)))
+}
+
 pub fn rust_series_to_py_series(series: &Series) -> PyResult<PyObject> {
     // ensure we have a single chunk
     let series = series.rechunk();
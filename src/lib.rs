@@ -1,25 +1,82 @@
 mod df;
+mod errors;
 mod exposure;
 mod ffi;
+mod sets;
+mod stats;
+mod threads;
+use errors::{
+    BelindaError, ClusteringFormatError, GraphMismatchError, InvalidSetColumnError,
+    UnknownNodeError,
+};
 use exposure::{
-    py_bitmap_union, py_from_memberships, py_label_cc, py_label_cc_size, py_nodeset_to_list,
-    py_popcnt, py_read_membership_file, py_read_json, set_nthreads, Graph, SingletonMode,
+    py_bitmap_intersection, py_bitmap_union, py_from_memberships, py_label_cc, py_label_cc_size,
+    py_nodeset_to_list, py_popcnt, py_read_json, py_read_membership_file,
+    py_read_parquet_clustering, py_validate, py_write_json, py_write_membership,
+    py_write_parquet_clustering, Graph, SingletonMode, UnknownNodePolicy,
 };
 use pyo3::prelude::*;
+use sets::{
+    py_set_contains, py_set_contains_any, py_set_difference, py_set_intersection,
+    py_set_intersection_len, py_set_is_subset, py_set_jaccard, py_set_max, py_set_min,
+    py_set_overlap_coefficient, py_set_repr, py_set_sample, py_set_select,
+    py_set_symmetric_difference,
+};
+use stats::{py_compute_cpm, py_compute_modularity, py_compute_stats, py_split_disconnected};
+use threads::{get_nthreads, py_threads, set_nthreads, ThreadLimit};
 
 /// A Python module implemented in Rust.
 #[pymodule]
-fn belinda(_py: Python, m: &PyModule) -> PyResult<()> {
+fn belinda(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<Graph>()?;
     m.add_class::<SingletonMode>()?;
+    m.add_class::<UnknownNodePolicy>()?;
+    m.add("BelindaError", py.get_type::<BelindaError>())?;
+    m.add(
+        "ClusteringFormatError",
+        py.get_type::<ClusteringFormatError>(),
+    )?;
+    m.add("UnknownNodeError", py.get_type::<UnknownNodeError>())?;
+    m.add(
+        "InvalidSetColumnError",
+        py.get_type::<InvalidSetColumnError>(),
+    )?;
+    m.add("GraphMismatchError", py.get_type::<GraphMismatchError>())?;
+    m.add_class::<ThreadLimit>()?;
     m.add_function(wrap_pyfunction!(set_nthreads, m)?)?;
+    m.add_function(wrap_pyfunction!(get_nthreads, m)?)?;
+    m.add_function(wrap_pyfunction!(py_threads, m)?)?;
     m.add_function(wrap_pyfunction!(py_popcnt, m)?)?;
     m.add_function(wrap_pyfunction!(py_bitmap_union, m)?)?;
+    m.add_function(wrap_pyfunction!(py_bitmap_intersection, m)?)?;
     m.add_function(wrap_pyfunction!(py_from_memberships, m)?)?;
     m.add_function(wrap_pyfunction!(py_read_json, m)?)?;
+    m.add_function(wrap_pyfunction!(py_write_json, m)?)?;
     m.add_function(wrap_pyfunction!(py_read_membership_file, m)?)?;
+    m.add_function(wrap_pyfunction!(py_write_membership, m)?)?;
+    m.add_function(wrap_pyfunction!(py_read_parquet_clustering, m)?)?;
+    m.add_function(wrap_pyfunction!(py_write_parquet_clustering, m)?)?;
     m.add_function(wrap_pyfunction!(py_label_cc, m)?)?;
     m.add_function(wrap_pyfunction!(py_label_cc_size, m)?)?;
     m.add_function(wrap_pyfunction!(py_nodeset_to_list, m)?)?;
+    m.add_function(wrap_pyfunction!(py_validate, m)?)?;
+    m.add_function(wrap_pyfunction!(py_compute_stats, m)?)?;
+    m.add_function(wrap_pyfunction!(py_compute_modularity, m)?)?;
+    m.add_function(wrap_pyfunction!(py_compute_cpm, m)?)?;
+    m.add_function(wrap_pyfunction!(py_split_disconnected, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_intersection, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_difference, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_symmetric_difference, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_is_subset, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_intersection_len, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_jaccard, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_overlap_coefficient, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_contains, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_contains_any, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_repr, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_min, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_max, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_select, m)?)?;
+    m.add_function(wrap_pyfunction!(py_set_sample, m)?)?;
     Ok(())
 }
@@ -0,0 +1,329 @@
+use aocluster::aoc::rayon::prelude::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
+use itertools::Itertools;
+use polars::prelude::*;
+use pyo3::prelude::*;
+use rand::{seq::index, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use roaring::RoaringBitmap;
+
+use crate::{
+    df::{
+        build_series_of_kind, collect_sets, column_meta, combine_fingerprints, combine_kinds,
+        EfficientSet, SetKind,
+    },
+    errors::{to_py_err, Error},
+    exposure::Graph,
+    ffi, threads,
+};
+
+/// Applies `op` to each pair of rows of two set columns. A column of length one is paired
+/// with every row of the other one. Also returns the kind and the graph fingerprint shared by
+/// both columns.
+fn pairwise<T, F>(lhs: &Series, rhs: &Series, op: F) -> anyhow::Result<(Vec<T>, SetKind, u64)>
+where
+    T: Send,
+    F: Fn(&EfficientSet, &EfficientSet) -> T + Send + Sync,
+{
+    let (lhs_kind, lhs_fingerprint) = column_meta(lhs)?;
+    let (rhs_kind, rhs_fingerprint) = column_meta(rhs)?;
+    let kind = combine_kinds(lhs_kind, rhs_kind)?;
+    let fingerprint = combine_fingerprints(lhs_fingerprint, rhs_fingerprint)?;
+    let lhs = collect_sets(lhs)?;
+    let rhs = collect_sets(rhs)?;
+    let n = match (lhs.len(), rhs.len()) {
+        (a, b) if a == b => a,
+        (1, b) => b,
+        (a, 1) => a,
+        (a, b) => {
+            return Err(Error::InvalidSetColumn(format!(
+                "cannot pair up set columns of lengths {} and {}",
+                a, b
+            ))
+            .into())
+        }
+    };
+    let pick = |sets: &[EfficientSet], i: usize| if sets.len() == 1 { 0 } else { i };
+    let out = threads::install(|| {
+        (0..n)
+            .into_par_iter()
+            .map(|i| op(&lhs[pick(&lhs, i)], &rhs[pick(&rhs, i)]))
+            .collect()
+    });
+    Ok((out, kind, fingerprint))
+}
+
+pub fn rust_set_intersection(lhs: &Series, rhs: &Series) -> anyhow::Result<Series> {
+    let (sets, kind, fingerprint) = pairwise(lhs, rhs, |a, b| a.intersection(b))?;
+    Ok(build_series_of_kind(sets, kind, fingerprint))
+}
+
+pub fn rust_set_difference(lhs: &Series, rhs: &Series) -> anyhow::Result<Series> {
+    let (sets, kind, fingerprint) = pairwise(lhs, rhs, |a, b| a.difference(b))?;
+    Ok(build_series_of_kind(sets, kind, fingerprint))
+}
+
+pub fn rust_set_symmetric_difference(lhs: &Series, rhs: &Series) -> anyhow::Result<Series> {
+    let (sets, kind, fingerprint) = pairwise(lhs, rhs, |a, b| a.symmetric_difference(b))?;
+    Ok(build_series_of_kind(sets, kind, fingerprint))
+}
+
+pub fn rust_set_is_subset(lhs: &Series, rhs: &Series) -> anyhow::Result<Series> {
+    let (mask, ..) = pairwise(lhs, rhs, |a, b| a.is_subset(b))?;
+    Ok(Series::new("is_subset", mask))
+}
+
+pub fn rust_set_intersection_len(lhs: &Series, rhs: &Series) -> anyhow::Result<Series> {
+    let (lens, ..) = pairwise(lhs, rhs, |a, b| a.intersection_len(b))?;
+    Ok(Series::new("intersection_len", lens))
+}
+
+/// `|A ∩ B| / |A ∪ B|`, null when both sets are empty
+pub fn rust_set_jaccard(lhs: &Series, rhs: &Series) -> anyhow::Result<Series> {
+    let (scores, ..) = pairwise(lhs, rhs, |a, b| {
+        let shared = a.intersection_len(b);
+        let total = a.len() + b.len() - shared;
+        (total > 0).then(|| shared as f64 / total as f64)
+    })?;
+    Ok(Series::new("jaccard", scores))
+}
+
+/// `|A ∩ B| / min(|A|, |B|)`, null when either set is empty
+pub fn rust_set_overlap_coefficient(lhs: &Series, rhs: &Series) -> anyhow::Result<Series> {
+    let (scores, ..) = pairwise(lhs, rhs, |a, b| {
+        let smaller = a.len().min(b.len());
+        (smaller > 0).then(|| a.intersection_len(b) as f64 / smaller as f64)
+    })?;
+    Ok(Series::new("overlap_coefficient", scores))
+}
+
+/// Whether each set contains `node` (an original node id). Nodes not in the graph are in no set.
+pub fn rust_set_contains(g: &Graph, series: &Series, node: u32) -> anyhow::Result<Series> {
+    let id = g.find_internal_id(node);
+    let mask = g
+        .sets_of(series)?
+        .iter()
+        .map(|set| id.map_or(false, |u| set.contains(u as u64)))
+        .collect_vec();
+    Ok(Series::new("contains", mask))
+}
+
+/// Whether each set contains at least one of `nodes` (original node ids), ignoring the nodes
+/// not in the graph
+pub fn rust_set_contains_any(g: &Graph, series: &Series, nodes: &Series) -> anyhow::Result<Series> {
+    let nodes = nodes.cast(&DataType::UInt32)?;
+    let wanted: RoaringBitmap = nodes
+        .u32()?
+        .into_iter()
+        .flatten()
+        .filter_map(|v| g.find_internal_id(v))
+        .collect();
+    let wanted = EfficientSet::SmallSet(wanted);
+    let mask = g
+        .sets_of(series)?
+        .iter()
+        .map(|set| !set.is_disjoint(&wanted))
+        .collect_vec();
+    Ok(Series::new("contains_any", mask))
+}
+
+/// The size and the first `limit` members of each set, e.g. `n=4 {0, 1, 2, ...}`. Members are
+/// shown as original node ids if `g` is given, internal ids otherwise.
+pub fn rust_set_repr(g: Option<&Graph>, series: &Series, limit: usize) -> anyhow::Result<Series> {
+    let sets = match g {
+        Some(g) => g.sets_of(series)?,
+        None => collect_sets(series)?,
+    };
+    let reprs = sets
+        .iter()
+        .map(|set| {
+            let mut members = set
+                .iter()
+                .take(limit)
+                .map(|u| g.map_or(u, |g| g.original_id(u)).to_string())
+                .collect_vec();
+            if set.len() > limit as u64 {
+                members.push("...".to_string());
+            }
+            format!("n={} {{{}}}", set.len(), members.join(", "))
+        })
+        .collect_vec();
+    Ok(Series::new("repr", reprs))
+}
+
+/// The original id of the member `pick` chooses in each set, null if it chooses none
+fn pick_member<F>(g: &Graph, series: &Series, pick: F) -> anyhow::Result<Series>
+where
+    F: Fn(&RoaringBitmap) -> Option<u32> + Send + Sync,
+{
+    let bitmaps = g.bitmaps_of(series)?;
+    let members: Vec<Option<u32>> = threads::install(|| {
+        bitmaps
+            .par_iter()
+            .map(|bm| pick(bm).map(|u| g.original_id(u as u64) as u32))
+            .collect()
+    });
+    Ok(Series::new("node", members))
+}
+
+pub fn rust_set_min(g: &Graph, series: &Series) -> anyhow::Result<Series> {
+    pick_member(g, series, RoaringBitmap::min)
+}
+
+pub fn rust_set_max(g: &Graph, series: &Series) -> anyhow::Result<Series> {
+    pick_member(g, series, RoaringBitmap::max)
+}
+
+/// The member of rank `rank` (0 being the smallest internal id) of each set, null if the set is
+/// not that large
+pub fn rust_set_select(g: &Graph, series: &Series, rank: u32) -> anyhow::Result<Series> {
+    pick_member(g, series, |bm| bm.select(rank))
+}
+
+/// `k` members of each set drawn uniformly without replacement (all of them for smaller sets),
+/// sorted by internal id. The draws only depend on `seed` and the position of the set, each
+/// position using its own ChaCha8 stream.
+pub fn rust_set_sample(g: &Graph, series: &Series, k: usize, seed: u64) -> anyhow::Result<Series> {
+    let bitmaps = g.bitmaps_of(series)?;
+    let samples: Vec<Series> = threads::install(|| {
+        bitmaps
+            .par_iter()
+            .enumerate()
+            .map(|(i, bm)| {
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                rng.set_stream(i as u64);
+                let n = bm.len() as usize;
+                let mut ranks = index::sample(&mut rng, n, k.min(n)).into_vec();
+                ranks.sort_unstable();
+                ranks
+                    .into_iter()
+                    .map(|r| g.original_id(bm.select(r as u32).unwrap() as u64) as u32)
+                    .collect()
+            })
+            .collect()
+    });
+    Ok(Series::new("sample", samples))
+}
+
+fn binary_op(
+    py: Python,
+    lhs: &PyAny,
+    rhs: &PyAny,
+    op: fn(&Series, &Series) -> anyhow::Result<Series>,
+) -> PyResult<PyObject> {
+    let lhs = ffi::py_series_to_rust_series(lhs)?;
+    let rhs = ffi::py_series_to_rust_series(rhs)?;
+    let out = py.allow_threads(|| op(&lhs, &rhs)).map_err(to_py_err)?;
+    ffi::rust_series_to_py_series(&out)
+}
+
+#[pyfunction(name = "intersection")]
+pub fn py_set_intersection(py: Python, lhs: &PyAny, rhs: &PyAny) -> PyResult<PyObject> {
+    binary_op(py, lhs, rhs, rust_set_intersection)
+}
+
+#[pyfunction(name = "difference")]
+pub fn py_set_difference(py: Python, lhs: &PyAny, rhs: &PyAny) -> PyResult<PyObject> {
+    binary_op(py, lhs, rhs, rust_set_difference)
+}
+
+#[pyfunction(name = "symmetric_difference")]
+pub fn py_set_symmetric_difference(py: Python, lhs: &PyAny, rhs: &PyAny) -> PyResult<PyObject> {
+    binary_op(py, lhs, rhs, rust_set_symmetric_difference)
+}
+
+#[pyfunction(name = "is_subset")]
+pub fn py_set_is_subset(py: Python, lhs: &PyAny, rhs: &PyAny) -> PyResult<PyObject> {
+    binary_op(py, lhs, rhs, rust_set_is_subset)
+}
+
+#[pyfunction(name = "intersection_len")]
+pub fn py_set_intersection_len(py: Python, lhs: &PyAny, rhs: &PyAny) -> PyResult<PyObject> {
+    binary_op(py, lhs, rhs, rust_set_intersection_len)
+}
+
+#[pyfunction(name = "jaccard")]
+pub fn py_set_jaccard(py: Python, lhs: &PyAny, rhs: &PyAny) -> PyResult<PyObject> {
+    binary_op(py, lhs, rhs, rust_set_jaccard)
+}
+
+#[pyfunction(name = "overlap_coefficient")]
+pub fn py_set_overlap_coefficient(py: Python, lhs: &PyAny, rhs: &PyAny) -> PyResult<PyObject> {
+    binary_op(py, lhs, rhs, rust_set_overlap_coefficient)
+}
+
+#[pyfunction(name = "contains")]
+pub fn py_set_contains(py: Python, g: &Graph, series: &PyAny, node: u32) -> PyResult<PyObject> {
+    let series = ffi::py_series_to_rust_series(series)?;
+    let out = py
+        .allow_threads(|| rust_set_contains(g, &series, node))
+        .map_err(to_py_err)?;
+    ffi::rust_series_to_py_series(&out)
+}
+
+#[pyfunction(name = "contains_any")]
+pub fn py_set_contains_any(
+    py: Python,
+    g: &Graph,
+    series: &PyAny,
+    nodes: &PyAny,
+) -> PyResult<PyObject> {
+    let series = ffi::py_series_to_rust_series(series)?;
+    let nodes = ffi::py_series_to_rust_series(nodes)?;
+    let out = py
+        .allow_threads(|| rust_set_contains_any(g, &series, &nodes))
+        .map_err(to_py_err)?;
+    ffi::rust_series_to_py_series(&out)
+}
+
+#[pyfunction(name = "set_repr", g = "None", limit = "5")]
+pub fn py_set_repr(
+    py: Python,
+    series: &PyAny,
+    g: Option<&Graph>,
+    limit: usize,
+) -> PyResult<PyObject> {
+    let series = ffi::py_series_to_rust_series(series)?;
+    let out = py
+        .allow_threads(|| rust_set_repr(g, &series, limit))
+        .map_err(to_py_err)?;
+    ffi::rust_series_to_py_series(&out)
+}
+
+fn pick_op(
+    py: Python,
+    g: &Graph,
+    series: &PyAny,
+    op: impl Fn(&Graph, &Series) -> anyhow::Result<Series> + Send + Sync,
+) -> PyResult<PyObject> {
+    let series = ffi::py_series_to_rust_series(series)?;
+    let out = py.allow_threads(|| op(g, &series)).map_err(to_py_err)?;
+    ffi::rust_series_to_py_series(&out)
+}
+
+#[pyfunction(name = "set_min")]
+pub fn py_set_min(py: Python, g: &Graph, series: &PyAny) -> PyResult<PyObject> {
+    pick_op(py, g, series, rust_set_min)
+}
+
+#[pyfunction(name = "set_max")]
+pub fn py_set_max(py: Python, g: &Graph, series: &PyAny) -> PyResult<PyObject> {
+    pick_op(py, g, series, rust_set_max)
+}
+
+#[pyfunction(name = "set_select")]
+pub fn py_set_select(py: Python, g: &Graph, series: &PyAny, rank: u32) -> PyResult<PyObject> {
+    pick_op(py, g, series, |g, s| rust_set_select(g, s, rank))
+}
+
+#[pyfunction(name = "set_sample", seed = "0")]
+pub fn py_set_sample(
+    py: Python,
+    g: &Graph,
+    series: &PyAny,
+    k: usize,
+    seed: u64,
+) -> PyResult<PyObject> {
+    pick_op(py, g, series, |g, s| rust_set_sample(g, s, k, seed))
+}
@@ -0,0 +1,410 @@
+use aocluster::aoc::rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use itertools::Itertools;
+use polars::prelude::*;
+use pyo3::{
+    exceptions::{PyUserWarning, PyValueError},
+    prelude::*,
+};
+use roaring::RoaringBitmap;
+use std::collections::VecDeque;
+
+use crate::{
+    df::build_series_from_bitmap,
+    errors::to_py_err,
+    exposure::Graph,
+    ffi::{self, translate_df},
+    threads,
+};
+
+/// A per-cluster statistic, stored in the column of the same name
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stat {
+    /// Number of nodes
+    N,
+    /// Number of internal edges
+    M,
+    /// Number of edges leaving the cluster
+    C,
+    /// Minimum degree of the subgraph induced on the cluster
+    Mcd,
+    /// Number of triangles inside the cluster
+    Triangles,
+    /// Number of connected components of the subgraph induced on the cluster
+    NComponents,
+    /// Whether that subgraph is connected, i.e., has exactly one component
+    IsConnected,
+    /// Sizes of its connected components, largest first
+    ComponentSizes,
+}
+
+impl Stat {
+    /// Computed by the readers unless told otherwise
+    pub const DEFAULT: [Stat; 4] = [Stat::N, Stat::M, Stat::C, Stat::Mcd];
+    pub const ALL: [Stat; 8] = [
+        Stat::N,
+        Stat::M,
+        Stat::C,
+        Stat::Mcd,
+        Stat::Triangles,
+        Stat::NComponents,
+        Stat::IsConnected,
+        Stat::ComponentSizes,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stat::N => "n",
+            Stat::M => "m",
+            Stat::C => "c",
+            Stat::Mcd => "mcd",
+            Stat::Triangles => "triangles",
+            Stat::NComponents => "n_components",
+            Stat::IsConnected => "is_connected",
+            Stat::ComponentSizes => "component_sizes",
+        }
+    }
+
+    /// Whether the statistic comes from the internal degrees of the nodes
+    fn needs_degrees(self) -> bool {
+        matches!(self, Stat::M | Stat::C | Stat::Mcd)
+    }
+
+    fn needs_components(self) -> bool {
+        matches!(
+            self,
+            Stat::NComponents | Stat::IsConnected | Stat::ComponentSizes
+        )
+    }
+}
+
+/// The statistics named in `stats`, or the default ones if not given
+pub fn parse_stats(stats: Option<Vec<&str>>) -> PyResult<Vec<Stat>> {
+    let stats = match stats {
+        Some(stats) => stats,
+        None => return Ok(Stat::DEFAULT.to_vec()),
+    };
+    stats
+        .into_iter()
+        .map(|name| {
+            Stat::ALL
+                .into_iter()
+                .find(|it| it.name() == name)
+                .ok_or_else(|| {
+                    let known = Stat::ALL.map(Stat::name).join(", ");
+                    PyValueError::new_err(format!(
+                        "unknown statistic {:?} (known: {})",
+                        name, known
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// Everything a single pass over a cluster can compute; only the requested fields are filled in
+#[derive(Default)]
+struct ClusterStats {
+    n: u64,
+    m: u64,
+    c: u64,
+    mcd: u64,
+    triangles: u64,
+    /// Component sizes, largest first
+    components: Vec<u64>,
+}
+
+fn cluster_stats(adj: &[RoaringBitmap], nodes: &RoaringBitmap, stats: &[Stat]) -> ClusterStats {
+    let mut out = ClusterStats {
+        n: nodes.len(),
+        ..Default::default()
+    };
+    if stats.iter().any(|it| it.needs_degrees()) {
+        let mut mcd = None;
+        for u in nodes.iter() {
+            let neighbors = &adj[u as usize];
+            let inside = neighbors.intersection_len(nodes);
+            out.m += inside;
+            out.c += neighbors.len() - inside;
+            mcd = Some(mcd.map_or(inside, |it: u64| it.min(inside)));
+        }
+        out.m /= 2;
+        out.mcd = mcd.unwrap_or(0);
+    }
+    if stats.contains(&Stat::Triangles) {
+        for u in nodes.iter() {
+            let inside = &adj[u as usize] & nodes;
+            for v in inside.iter().filter(|&v| v > u) {
+                // counting each triangle u < v < w once
+                out.triangles += (&inside & &adj[v as usize]).range_cardinality(v + 1..);
+            }
+        }
+    }
+    if stats.iter().any(|it| it.needs_components()) {
+        out.components = components(adj, nodes).iter().map(|it| it.len()).collect();
+    }
+    out
+}
+
+/// The connected components of the subgraph induced on `nodes`, largest first, found by
+/// breadth-first search
+fn components(adj: &[RoaringBitmap], nodes: &RoaringBitmap) -> Vec<RoaringBitmap> {
+    let mut unseen = nodes.clone();
+    let mut found = vec![];
+    let mut queue = VecDeque::new();
+    while let Some(start) = unseen.min() {
+        unseen.remove(start);
+        queue.push_back(start);
+        let mut component = RoaringBitmap::new();
+        while let Some(u) = queue.pop_front() {
+            component.insert(u);
+            let next = &adj[u as usize] & &unseen;
+            unseen -= &next;
+            queue.extend(next.iter());
+        }
+        found.push(component);
+    }
+    // stable, so ties stay ordered by their smallest node
+    found.sort_by(|a, b| b.len().cmp(&a.len()));
+    found
+}
+
+/// Adds (or replaces) the columns of `stats` for the clusters in the `nodes` column of `df`, all
+/// computed in one parallel pass
+pub fn compute_stats(g: &Graph, df: &mut DataFrame, stats: &[Stat]) -> anyhow::Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let bitmaps = g.bitmaps_of(df.column("nodes")?)?;
+    let adj = g.get_adjacency();
+    let data: Vec<ClusterStats> = threads::install(|| {
+        bitmaps
+            .into_par_iter()
+            .map(|nodes| cluster_stats(adj, &nodes, stats))
+            .collect()
+    });
+    for &stat in stats {
+        let name = stat.name();
+        let column = match stat {
+            Stat::N => Series::new(name, data.iter().map(|it| it.n).collect_vec()),
+            Stat::M => Series::new(name, data.iter().map(|it| it.m).collect_vec()),
+            Stat::C => Series::new(name, data.iter().map(|it| it.c).collect_vec()),
+            Stat::Mcd => Series::new(name, data.iter().map(|it| it.mcd).collect_vec()),
+            Stat::Triangles => Series::new(name, data.iter().map(|it| it.triangles).collect_vec()),
+            Stat::NComponents => {
+                let counts = data.iter().map(|it| it.components.len() as u64);
+                Series::new(name, counts.collect_vec())
+            }
+            Stat::IsConnected => {
+                let flags = data.iter().map(|it| it.components.len() == 1);
+                Series::new(name, flags.collect_vec())
+            }
+            Stat::ComponentSizes => {
+                let sizes = data.iter().map(|it| Series::new("", &it.components));
+                Series::new(name, sizes.collect_vec())
+            }
+        };
+        df.with_column(column)?;
+    }
+    Ok(())
+}
+
+/// Replaces each cluster whose induced subgraph is disconnected with one row per component,
+/// labelled `<label>_<i>` (`i` being 0 for the largest component). Other columns are copied from
+/// the original row, except for the statistics, which are recomputed. Labels become strings.
+pub fn split_disconnected(g: &Graph, df: &DataFrame) -> anyhow::Result<DataFrame> {
+    let bitmaps = g.bitmaps_of(df.column("nodes")?)?;
+    let adj = g.get_adjacency();
+    let parts: Vec<Vec<RoaringBitmap>> = threads::install(|| {
+        bitmaps
+            .into_par_iter()
+            .map(|nodes| components(adj, &nodes))
+            .collect()
+    });
+    let labels = df.column("label")?.cast(&DataType::Utf8)?;
+    let mut rows = vec![];
+    let mut new_labels = vec![];
+    let mut nodes = vec![];
+    for (i, (label, mut parts)) in labels.utf8()?.into_iter().zip(parts).enumerate() {
+        if parts.len() <= 1 {
+            rows.push(i as u32);
+            new_labels.push(label.map(str::to_string));
+            nodes.push(parts.pop().unwrap_or_default());
+            continue;
+        }
+        for (j, part) in parts.into_iter().enumerate() {
+            rows.push(i as u32);
+            new_labels.push(label.map(|it| format!("{}_{}", it, j)));
+            nodes.push(part);
+        }
+    }
+    let mut out = df.take(&UInt32Chunked::from_vec("rows", rows))?;
+    out.with_column(Series::new("label", new_labels))?;
+    out.with_column(build_series_from_bitmap(nodes, g.get_fingerprint()))?;
+    let stale = Stat::ALL
+        .into_iter()
+        .filter(|it| df.column(it.name()).is_ok())
+        .collect_vec();
+    compute_stats(g, &mut out, &stale)?;
+    Ok(out)
+}
+
+#[pyfunction(name = "split_disconnected")]
+pub fn py_split_disconnected(py: Python, g: &Graph, clus: &PyAny) -> PyResult<PyObject> {
+    let df = ffi::py_df_to_rust_df(clus)?;
+    let mut out = py
+        .allow_threads(|| split_disconnected(g, &df))
+        .map_err(to_py_err)?;
+    translate_df(&mut out)
+}
+
+/// Returns `clus` with the statistics named in `stats` added
+#[pyfunction(name = "compute_stats")]
+pub fn py_compute_stats(
+    py: Python,
+    g: &Graph,
+    clus: &PyAny,
+    stats: Vec<&str>,
+) -> PyResult<PyObject> {
+    let stats = parse_stats(Some(stats))?;
+    let mut df = ffi::py_df_to_rust_df(clus)?;
+    py.allow_threads(|| compute_stats(g, &mut df, &stats))
+        .map_err(to_py_err)?;
+    translate_df(&mut df)
+}
+
+/// Sums over a cluster, each node `u` weighted by its belonging coefficient `a_u`
+#[derive(Default)]
+struct ClusterQuality {
+    /// Sum of `a_u * a_v` over the internal edges `uv`
+    internal: f64,
+    /// Sum of `a_u * deg(u)`
+    volume: f64,
+    /// Sum of `a_u`
+    size: f64,
+    /// Sum of `a_u^2`
+    size_sq: f64,
+}
+
+impl ClusterQuality {
+    /// Contribution to the modularity, `m` being the number of edges of the graph
+    fn modularity(&self, m: f64, r: f64) -> f64 {
+        self.internal / m - r * (self.volume / (2.0 * m)).powi(2)
+    }
+
+    /// `internal - r * (sum of a_u * a_v over the pairs of nodes u != v)`
+    fn cpm(&self, r: f64) -> f64 {
+        self.internal - r * (self.size.powi(2) - self.size_sq) / 2.0
+    }
+}
+
+fn cluster_quality(
+    adj: &[RoaringBitmap],
+    nodes: &RoaringBitmap,
+    belonging: &[f64],
+) -> ClusterQuality {
+    let mut out = ClusterQuality::default();
+    for u in nodes.iter() {
+        let a = belonging[u as usize];
+        let neighbors = &adj[u as usize];
+        out.volume += a * neighbors.len() as f64;
+        out.size += a;
+        out.size_sq += a * a;
+        for v in (neighbors & nodes).iter().filter(|&v| v > u) {
+            out.internal += a * belonging[v as usize];
+        }
+    }
+    out
+}
+
+/// The quality sums of each cluster in `nodes`, also returning the number of nodes in more than
+/// one cluster. With `overlapping`, each node is weighted by the inverse of the number of
+/// clusters containing it, which gives the usual scores for partitions.
+fn cluster_qualities(
+    g: &Graph,
+    nodes: &Series,
+    overlapping: bool,
+) -> anyhow::Result<(Vec<ClusterQuality>, u64)> {
+    let bitmaps = g.bitmaps_of(nodes)?;
+    let adj = g.get_adjacency();
+    let mut memberships = vec![0u32; adj.len()];
+    for u in bitmaps.iter().flat_map(|it| it.iter()) {
+        memberships[u as usize] += 1;
+    }
+    let shared = memberships.iter().filter(|&&it| it > 1).count() as u64;
+    let belonging = memberships
+        .iter()
+        .map(|&it| {
+            if overlapping && it > 1 {
+                1.0 / it as f64
+            } else {
+                1.0
+            }
+        })
+        .collect::<Vec<_>>();
+    let qualities = threads::install(|| {
+        bitmaps
+            .into_par_iter()
+            .map(|nodes| cluster_quality(adj, &nodes, &belonging))
+            .collect()
+    });
+    Ok((qualities, shared))
+}
+
+/// Adds the `name` column scoring each cluster of `clus`, warning if the clusters overlap while
+/// `overlapping` is off
+fn with_quality<F>(
+    py: Python,
+    g: &Graph,
+    clus: &PyAny,
+    overlapping: bool,
+    name: &str,
+    score: F,
+) -> PyResult<PyObject>
+where
+    F: Fn(&ClusterQuality) -> f64 + Send,
+{
+    let mut df = ffi::py_df_to_rust_df(clus)?;
+    let shared = py
+        .allow_threads(|| -> anyhow::Result<u64> {
+            let (qualities, shared) = cluster_qualities(g, df.column("nodes")?, overlapping)?;
+            let scores: Vec<f64> = qualities.iter().map(score).collect();
+            df.with_column(Series::new(name, scores))?;
+            Ok(shared)
+        })
+        .map_err(to_py_err)?;
+    if shared > 0 && !overlapping {
+        let msg = format!(
+            "the clusters are not a partition ({} nodes are in several clusters), pass \
+             overlapping=True to compute {} for a cover",
+            shared, name
+        );
+        PyErr::warn(py, py.get_type::<PyUserWarning>(), &msg, 1)?;
+    }
+    translate_df(&mut df)
+}
+
+/// Returns `clus` with the modularity of each cluster (summing up to that of the clustering)
+#[pyfunction(name = "compute_modularity", r = "1.0", overlapping = "false")]
+pub fn py_compute_modularity(
+    py: Python,
+    g: &Graph,
+    clus: &PyAny,
+    r: f64,
+    overlapping: bool,
+) -> PyResult<PyObject> {
+    let m = g.m() as f64;
+    with_quality(py, g, clus, overlapping, "modularity", |it| {
+        it.modularity(m, r)
+    })
+}
+
+/// Returns `clus` with the CPM score of each cluster (summing up to that of the clustering)
+#[pyfunction(name = "compute_cpm", overlapping = "false")]
+pub fn py_compute_cpm(
+    py: Python,
+    g: &Graph,
+    clus: &PyAny,
+    r: f64,
+    overlapping: bool,
+) -> PyResult<PyObject> {
+    with_quality(py, g, clus, overlapping, "cpm", |it| it.cpm(r))
+}
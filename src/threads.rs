@@ -0,0 +1,90 @@
+use aocluster::aoc::rayon::{self, ThreadPool, ThreadPoolBuilder};
+use pyo3::prelude::*;
+use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+};
+
+use crate::errors::to_py_err;
+
+/// Pool set by `set_nthreads`, used when no `threads(n)` block is active
+static DEFAULT_POOL: Mutex<Option<Arc<ThreadPool>>> = Mutex::new(None);
+thread_local! {
+    /// Pools of the `threads(n)` blocks entered by this (Python) thread, innermost last. Kept
+    /// per thread so that a block only limits the work started from its own thread.
+    static SCOPED_POOLS: RefCell<Vec<Arc<ThreadPool>>> = RefCell::new(Vec::new());
+}
+
+fn build_pool(nthreads: usize) -> anyhow::Result<Arc<ThreadPool>> {
+    let pool = ThreadPoolBuilder::new().num_threads(nthreads).build()?;
+    Ok(Arc::new(pool))
+}
+
+fn current_pool() -> Option<Arc<ThreadPool>> {
+    let scoped = SCOPED_POOLS.with(|pools| pools.borrow().last().cloned());
+    scoped.or_else(|| DEFAULT_POOL.lock().unwrap().clone())
+}
+
+/// Runs `op` inside the innermost active pool, or rayon's global pool if there is none
+pub fn install<R, F>(op: F) -> R
+where
+    R: Send,
+    F: FnOnce() -> R + Send,
+{
+    match current_pool() {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+#[pyfunction]
+pub fn set_nthreads(nthreads: usize) -> PyResult<()> {
+    let pool = build_pool(nthreads).map_err(to_py_err)?;
+    *DEFAULT_POOL.lock().unwrap() = Some(pool);
+    Ok(())
+}
+
+/// Number of threads parallel operations currently run on
+#[pyfunction]
+pub fn get_nthreads() -> usize {
+    match current_pool() {
+        Some(pool) => pool.current_num_threads(),
+        None => rayon::current_num_threads(),
+    }
+}
+
+/// Context manager limiting parallel operations to `nthreads` threads, e.g.
+/// `with bl.threads(4): ...`. Blocks can be nested.
+#[pyclass]
+pub struct ThreadLimit {
+    pool: Arc<ThreadPool>,
+}
+
+#[pymethods]
+impl ThreadLimit {
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        SCOPED_POOLS.with(|pools| pools.borrow_mut().push(slf.pool.clone()));
+        slf
+    }
+
+    fn __exit__(&self, _exc_type: &PyAny, _exc_value: &PyAny, _traceback: &PyAny) -> bool {
+        SCOPED_POOLS.with(|pools| {
+            let mut pools = pools.borrow_mut();
+            if let Some(i) = pools.iter().rposition(|it| Arc::ptr_eq(it, &self.pool)) {
+                pools.remove(i);
+            }
+        });
+        false
+    }
+
+    #[getter]
+    fn nthreads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+}
+
+#[pyfunction(name = "threads")]
+pub fn py_threads(nthreads: usize) -> PyResult<ThreadLimit> {
+    let pool = build_pool(nthreads).map_err(to_py_err)?;
+    Ok(ThreadLimit { pool })
+}
@@ -0,0 +1,18 @@
+from belinda import Graph, read_membership_series
+import polars as pl
+import pytest
+
+@pytest.fixture
+def simple_graph():
+    return Graph("resources/discont_graph.txt")
+
+@pytest.fixture
+def foreign_clusters():
+    # node sets of a larger graph, both holding the node 8 that `simple_graph` lacks, with the
+    # fingerprint cleared like in sets of an unknown graph
+    larger = Graph.from_edges(pl.Series("src", [0] * 9), pl.Series("dst", list(range(1, 10))))
+    c = read_membership_series(
+        larger, pl.Series([0, 8, 1, 8], dtype=pl.UInt32), pl.Series([1, 1, 2, 2], dtype=pl.UInt32)
+    ).sort("label")
+    nodes = [b[:6] + bytes(8) + b[14:] for b in c["nodes"].to_list()]
+    return c.select("label").with_column(pl.Series("nodes", nodes, dtype=pl.Binary))
@@ -1,11 +1,8 @@
 from belinda import *
 import numpy as np
+import threading
 import pytest
 
-@pytest.fixture
-def simple_graph():
-    return Graph("resources/discont_graph.txt")
-
 def test_graph_has_sane_information(simple_graph):
     summary = simple_graph.summary()
     assert summary["n"].view()[0] == 6
@@ -26,4 +23,180 @@ def test_autopopulate(simple_graph):
     cluster_sizes = c3.with_column(pl.col('nodes').set.popcnt().alias('cluster_size'))["cluster_size"].to_numpy()
     assert csize == simple_graph.n
     assert csize == sum_size
-    assert np.all(cluster_sizes >= 1)
\ No newline at end of file
+    assert np.all(cluster_sizes >= 1)
+
+def test_save_load_roundtrip(simple_graph, tmp_path):
+    out = str(tmp_path / "graph.lz4")
+    simple_graph.save(out)
+    g = Graph.load(out)
+    assert g.summary().frame_equal(simple_graph.summary())
+    assert g.nodes().frame_equal(simple_graph.nodes())
+
+def test_load_bare_aocluster_graph():
+    # written by aocluster itself, without the snapshot header
+    g = Graph.load("resources/com-dblp.bincode.lz4")
+    assert (g.n, g.m) == (317080, 1049866)
+
+def test_from_edges_matches_parser(simple_graph):
+    src = pl.Series("src", [0, 0, 0, 0, 0])
+    dst = pl.Series("dst", [1, 2, 3, 4, 99])
+    g = Graph.from_edges(src, dst)
+    assert g.summary().frame_equal(simple_graph.summary())
+    assert g.nodes().frame_equal(simple_graph.nodes())
+
+def test_validate(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+    issues = validate(simple_graph, c)
+    assert sorted(issues.filter(pl.col("issue") == "uncovered_node")["node"].to_list()) == [3, 4]
+    c3 = read_membership(simple_graph, "resources/discont_graph.clus.txt", mode=SingletonMode.AutoPopulate)
+    issues = validate(simple_graph, c3)
+    assert issues.filter(pl.col("issue") == "uncovered_node").height == 0
+    assert issues.filter(pl.col("issue") == "null_label").height == 2
+    overlapping = pl.concat([c, c])
+    issues = validate(simple_graph, overlapping)
+    assert issues.filter(pl.col("issue") == "duplicate_label").height == 2
+    assert issues.filter(pl.col("issue") == "overlapping_node").height == 8
+
+def test_validate_unknown_and_empty(simple_graph, foreign_clusters):
+    empty = foreign_clusters.head(1).select([
+        pl.lit(3).cast(pl.UInt32).alias("label"),
+        pl.col("nodes").set.difference(pl.col("nodes")).alias("nodes"),
+    ])
+    issues = validate(simple_graph, pl.concat([foreign_clusters, empty]))
+    unknown = issues.filter(pl.col("issue") == "unknown_node")
+    assert unknown["label"].to_list() == ["1", "2"]
+    assert unknown["detail"].to_list() == ["1 internal ids beyond the graph"] * 2
+    assert issues.filter(pl.col("issue") == "empty_cluster")["label"].to_list() == ["3"]
+    assert issues.filter(pl.col("issue") == "overlapping_node").height == 0
+    assert sorted(issues.filter(pl.col("issue") == "uncovered_node")["node"].to_list()) == [2, 3, 4, 99]
+
+@pytest.fixture
+def restore_nthreads():
+    # set_nthreads is process-wide, so put the previous count back for the tests that follow
+    nthreads = get_nthreads()
+    yield
+    set_nthreads(nthreads)
+
+def test_thread_limits(simple_graph, restore_nthreads):
+    set_nthreads(3)
+    set_nthreads(2)
+    assert get_nthreads() == 2
+    with threads(1):
+        assert get_nthreads() == 1
+        with threads(3):
+            assert get_nthreads() == 3
+        c = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+        assert len(c) == 2
+    assert get_nthreads() == 2
+
+def test_thread_limits_are_per_thread(restore_nthreads):
+    set_nthreads(2)
+    entered, checked = threading.Event(), threading.Event()
+    seen = {}
+
+    def limited():
+        with threads(1):
+            entered.set()
+            checked.wait(10)
+            seen["limited"] = get_nthreads()
+
+    def other():
+        entered.wait(10)
+        seen["other"] = get_nthreads()
+        checked.set()
+
+    workers = [threading.Thread(target=limited), threading.Thread(target=other)]
+    for t in workers:
+        t.start()
+    for t in workers:
+        t.join()
+    assert seen == {"limited": 1, "other": 2}
+
+def test_fingerprint(simple_graph, tmp_path):
+    out = str(tmp_path / "graph.lz4")
+    simple_graph.save(out)
+    assert Graph.load(out).fingerprint == simple_graph.fingerprint
+    other = Graph.from_edges(pl.Series("src", [0, 0, 1]), pl.Series("dst", [1, 2, 99]))
+    assert other.fingerprint != simple_graph.fingerprint
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+    with pytest.raises(GraphMismatchError):
+        other.nodes(c)
+    with pytest.raises(GraphMismatchError):
+        c.select(pl.col("nodes").set.flatten(other))
+    c_other = read_membership(other, "resources/discont_graph.clus.txt")
+    both = c.sort("label").with_column(c_other.sort("label")["nodes"].alias("other"))
+    with pytest.raises(GraphMismatchError):
+        both.select(pl.col("nodes").set.intersection(pl.col("other")))
+
+def test_drop_caches(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+    simple_graph.drop_caches()
+    again = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+    assert again.select(["label", "n", "m", "c", "mcd"]).frame_equal(c.select(["label", "n", "m", "c", "mcd"]))
+    assert simple_graph.num_components() == 1
+
+def test_cold_adjacency_cache(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt", stats=[]).sort("label")
+    with threads(2):
+        coverage = c.select(simple_graph.edge_coverage(overlap=True))["edge_coverage"].to_list()
+        assert coverage == [0.4]
+        edges = c.select(simple_graph.intra_edges(pl.col("nodes")).alias("edges"))["edges"]
+        simple_graph.drop_caches()
+        assert simple_graph.edges_of(edges).to_list() == [[{"u": 0, "v": 1}, {"u": 0, "v": 2}], []]
+
+def test_stats_selection(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt", stats=[])
+    assert c.columns == ["label", "nodes"]
+    c = compute_stats(simple_graph, c, ["n", "triangles", "mcd"]).sort("label")
+    assert c.columns == ["label", "nodes", "n", "triangles", "mcd"]
+    assert c["n"].to_list() == [3, 1]
+    assert c["mcd"].to_list() == [1, 0]
+    assert c["triangles"].to_list() == [0, 0]
+    with pytest.raises(ValueError):
+        compute_stats(simple_graph, c, ["diameter"])
+    g = Graph.from_edges(pl.Series("src", [0, 0, 1, 1]), pl.Series("dst", [1, 2, 2, 3]))
+    c = read_membership_series(
+        g, pl.Series([0, 1, 2, 3], dtype=pl.UInt32), pl.Series([1, 1, 1, 1], dtype=pl.UInt32), stats=["triangles"]
+    )
+    assert c["triangles"].to_list() == [1]
+
+def test_rust_modularity(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+    q = compute_modularity(simple_graph, c).with_column(simple_graph.modularity().alias("expected"))
+    assert q["modularity"].to_list() == pytest.approx(q["expected"].to_list())
+    assert q["modularity"].sum() == pytest.approx(-0.1)
+    cpm_scores = compute_cpm(simple_graph, c, 0.5).with_column(simple_graph.cpm(0.5).alias("expected"))
+    assert cpm_scores["cpm"].to_list() == pytest.approx(cpm_scores["expected"].to_list())
+    cover = read_membership_series(
+        simple_graph, pl.Series([0, 1, 2, 0, 99], dtype=pl.UInt32), pl.Series([1, 1, 1, 2, 2], dtype=pl.UInt32)
+    ).sort("label")
+    with pytest.warns(UserWarning):
+        compute_modularity(simple_graph, cover)
+    q = compute_modularity(simple_graph, cover, overlapping=True)
+    assert q["modularity"].to_list() == pytest.approx([-0.0025, -0.0225])
+    assert compute_cpm(simple_graph, cover, 0.5, overlapping=True)["cpm"].to_list() == pytest.approx([0.0, 0.25])
+
+def test_cluster_connectivity(simple_graph):
+    c = read_membership_series(
+        simple_graph,
+        pl.Series([0, 1, 2, 1, 2, 99], dtype=pl.UInt32),
+        pl.Series([1, 1, 1, 2, 2, 2], dtype=pl.UInt32),
+        stats=["n_components", "is_connected", "component_sizes"],
+    ).sort("label")
+    assert c["n_components"].to_list() == [1, 3]
+    assert c["is_connected"].to_list() == [True, False]
+    assert c["component_sizes"].to_list() == [[3], [1, 1, 1]]
+
+def test_split_disconnected(simple_graph):
+    c = read_membership_series(
+        simple_graph,
+        pl.Series([0, 1, 2, 1, 2, 99], dtype=pl.UInt32),
+        pl.Series([1, 1, 1, 2, 2, 2], dtype=pl.UInt32),
+    ).sort("label").with_column(pl.lit("leiden").alias("method"))
+    split = split_disconnected(simple_graph, c)
+    assert split["label"].to_list() == ["1", "2_0", "2_1", "2_2"]
+    assert split["n"].to_list() == [3, 1, 1, 1]
+    assert split["mcd"].to_list() == [1, 0, 0, 0]
+    assert split["method"].to_list() == ["leiden"] * 4
+    members = split.select(pl.col("nodes").set.flatten(simple_graph))["nodes"].to_list()
+    assert members == [[0, 1, 2], [1], [2], [99]]
@@ -0,0 +1,79 @@
+from belinda import *
+import polars as pl
+import pytest
+
+def test_write_membership_roundtrip(simple_graph, tmp_path):
+    c1 = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+    out = str(tmp_path / "clus.txt")
+    write_membership(simple_graph, c1, out)
+    c2 = read_membership(simple_graph, out)
+    assert c1.sort("label").select(["label", "n", "m", "c", "mcd"]).frame_equal(
+        c2.sort("label").select(["label", "n", "m", "c", "mcd"])
+    )
+
+def test_write_membership_string_labels(simple_graph, tmp_path):
+    c1 = read_membership(simple_graph, "resources/discont_graph.clus.txt", force_string_labels=True)
+    out = str(tmp_path / "clus.csv")
+    write_membership(simple_graph, c1, out, sep=",")
+    c2 = read_membership(simple_graph, out, sep=",", force_string_labels=True)
+    assert sorted(c1["label"].to_list()) == sorted(c2["label"].to_list())
+
+def test_write_json_roundtrip(simple_graph, tmp_path):
+    c1 = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+    c1 = c1.with_column((pl.col("m") * 2).alias("twice_m"))
+    out = str(tmp_path / "clus.json")
+    write_json(simple_graph, c1, out)
+    c2 = read_json(simple_graph, out)
+    assert c2.columns == c1.columns
+    cols = ["label", "n", "m", "c", "mcd", "twice_m"]
+    c1 = c1.sort("label").select(cols)
+    c2 = c2.sort("label").select(cols)
+    # JSON integers are read back as Int64; the statistics are recomputed with their own dtypes
+    assert c2.schema == {**c1.schema, "label": pl.Int64, "twice_m": pl.Int64}
+    assert c2.frame_equal(c1.with_columns([pl.col("label").cast(pl.Int64), pl.col("twice_m").cast(pl.Int64)]))
+
+def test_parquet_roundtrip(simple_graph, tmp_path):
+    c1 = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+    out = str(tmp_path / "clus.parquet")
+    write_parquet_clustering(simple_graph, c1, out)
+    raw = pl.read_parquet(out)
+    assert raw["nodes"].dtype == pl.List(pl.UInt32)
+    c2 = read_parquet_clustering(simple_graph, out)
+    cols = ["label", "n", "m", "c", "mcd"]
+    assert c1.sort("label").select(cols).to_dicts() == c2.sort("label").select(cols).to_dicts()
+
+def test_unknown_nodes_raise(simple_graph, tmp_path):
+    out = tmp_path / "bad.txt"
+    out.write_text("0\t1\n12345\t1\n12346\t1\n")
+    with pytest.raises(UnknownNodeError) as e:
+        read_membership(simple_graph, str(out))
+    assert "bad.txt" in str(e.value)
+    assert isinstance(e.value, BelindaError)
+
+def test_invalid_set_column_raises():
+    with pytest.raises(InvalidSetColumnError):
+        popcnt(pl.Series("nodes", [1, 2, 3]))
+
+def test_unknown_node_policies(simple_graph, tmp_path):
+    out = tmp_path / "bad.txt"
+    out.write_text("0\t1\n1\t1\n12345\t1\n12346\t1\n2\t2\n")
+    c = read_membership(simple_graph, str(out), policy=UnknownNodePolicy.Drop)
+    assert sorted(c["n"].to_list()) == [1, 2]
+    c, report = read_membership(simple_graph, str(out), policy=UnknownNodePolicy.DropAndReport)
+    assert sorted(c["n"].to_list()) == [1, 2]
+    assert sorted(report["unknown_node"].to_list()) == [12345, 12346]
+    assert report["label"].to_list() == [1, 1]
+    with pytest.raises(UnknownNodeError):
+        read_membership(simple_graph, str(out), policy=UnknownNodePolicy.Error)
+
+def test_sets_beyond_the_graph_raise(simple_graph, foreign_clusters, tmp_path):
+    with pytest.raises(InvalidSetColumnError):
+        foreign_clusters.select(pl.col("nodes").set.flatten(simple_graph))
+    with pytest.raises(InvalidSetColumnError):
+        compute_stats(simple_graph, foreign_clusters, ["n", "m"])
+    with pytest.raises(InvalidSetColumnError):
+        compute_modularity(simple_graph, foreign_clusters)
+    with pytest.raises(InvalidSetColumnError):
+        simple_graph.nodes(foreign_clusters)
+    with pytest.raises(InvalidSetColumnError):
+        write_membership(simple_graph, foreign_clusters, str(tmp_path / "clus.txt"))
@@ -0,0 +1,179 @@
+from belinda import *
+import polars as pl
+import pytest
+
+@pytest.fixture
+def paired(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+    ref = read_membership_series(
+        simple_graph, pl.Series([0, 1, 99, 3], dtype=pl.UInt32), pl.Series([1, 1, 2, 2], dtype=pl.UInt32)
+    )
+    return c.select(["label", "nodes"]).join(
+        ref.select(["label", pl.col("nodes").alias("reference")]), on="label"
+    ).sort("label")
+
+@pytest.fixture
+def empty_bigset(simple_graph):
+    # header (magic, BigSet variant, node kind, fingerprint) followed by an empty treemap
+    header = b"\xb1NS\x01\x01\x01" + simple_graph.fingerprint.to_bytes(8, "little")
+    return pl.lit(pl.Series("nodes", [header + bytes(8)], dtype=pl.Binary))
+
+def test_pairwise_set_algebra(paired):
+    sizes = paired.select([
+        pl.col("nodes").set.intersection(pl.col("reference")).set.popcnt().alias("intersection"),
+        pl.col("nodes").set.difference(pl.col("reference")).set.popcnt().alias("difference"),
+        pl.col("nodes").set.symmetric_difference("reference").set.popcnt().alias("symmetric_difference"),
+        pl.col("reference").set.is_subset(pl.col("nodes")).alias("is_subset"),
+    ])
+    assert sizes["intersection"].to_list() == [2, 1]
+    assert sizes["difference"].to_list() == [1, 0]
+    assert sizes["symmetric_difference"].to_list() == [1, 1]
+    assert sizes["is_subset"].to_list() == [True, False]
+
+def test_set_similarities(paired):
+    scores = paired.select([
+        pl.col("nodes").set.intersection_len(pl.col("reference")).alias("shared"),
+        pl.col("nodes").set.jaccard(pl.col("reference")).alias("jaccard"),
+        pl.col("nodes").set.overlap_coefficient(pl.col("reference")).alias("overlap"),
+    ])
+    assert scores["shared"].to_list() == [2, 1]
+    assert scores["jaccard"].to_list() == pytest.approx([2 / 3, 1 / 2])
+    assert scores["overlap"].to_list() == pytest.approx([1.0, 1.0])
+
+def test_contains(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt").sort("label")
+    masks = c.select([
+        pl.col("nodes").set.contains(simple_graph, 99).alias("has_99"),
+        pl.col("nodes").set.contains_any(simple_graph, [2, 99]).alias("has_2_or_99"),
+        pl.col("nodes").set.contains_any(simple_graph, [3, 4]).alias("has_3_or_4"),
+    ])
+    assert masks["has_99"].to_list() == [False, True]
+    assert masks["has_2_or_99"].to_list() == [True, True]
+    assert masks["has_3_or_4"].to_list() == [False, False]
+    unknown = c.select([
+        pl.col("nodes").set.contains(simple_graph, 12345).alias("has_12345"),
+        pl.col("nodes").set.contains_any(simple_graph, [12345, 99]).alias("has_12345_or_99"),
+    ])
+    assert unknown["has_12345"].to_list() == [False, False]
+    assert unknown["has_12345_or_99"].to_list() == [False, True]
+
+def test_clusters_of(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt")
+    found = simple_graph.clusters_of(pl.concat([c, c.with_column(pl.col("label") + 10)]), pl.Series([99, 3, 0]))
+    assert found["node"].to_list() == [99, 3, 0]
+    assert [sorted(it) for it in found["labels"].to_list()] == [[2, 12], [], [1, 11]]
+    found = simple_graph.clusters_of(c, pl.Series([99, None, 0], dtype=pl.UInt32))
+    assert found["node"].to_list() == [99, None, 0]
+    assert found["labels"].to_list() == [[2], None, [1]]
+
+def test_grouped_aggregations(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt", mode=SingletonMode.AutoPopulate)
+    grouped = (
+        c.lazy()
+        .groupby(pl.col("n") > 1)
+        .agg([
+            pl.col("nodes").set.union().set.popcnt().alias("union"),
+            pl.col("nodes").set.intersection().set.popcnt().alias("intersection"),
+        ])
+        .sort("n")
+        .collect()
+    )
+    assert grouped["union"].to_list() == [3, 3]
+    assert grouped["intersection"].to_list() == [0, 3]
+    whole = c.select(pl.col("nodes").set.intersection().set.popcnt().alias("intersection"))
+    assert whole["intersection"].to_list() == [0]
+
+def test_lazy_output_dtypes(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt").lazy()
+    q = c.select([
+        pl.col("nodes").set.popcnt().alias("size"),
+        pl.col("nodes").set.flatten(simple_graph).alias("members"),
+        pl.col("nodes").set.contains(simple_graph, 0).alias("has_0"),
+        simple_graph.intra_edges(pl.col("nodes")).alias("edges"),
+    ])
+    assert q.schema == {
+        "size": pl.UInt32,
+        "members": pl.List(pl.UInt32),
+        "has_0": pl.Boolean,
+        "edges": pl.Binary,
+    }
+    assert q.collect()["members"].to_list()[0] == [0, 1, 2]
+
+def test_repr(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt").sort("label")
+    reprs = c.select([
+        pl.col("nodes").set.repr(simple_graph).alias("full"),
+        pl.col("nodes").set.repr(simple_graph, limit=2).alias("short"),
+    ])
+    assert reprs["full"].to_list() == ["n=3 {0, 1, 2}", "n=1 {99}"]
+    assert reprs["short"].to_list() == ["n=3 {0, 1, ...}", "n=1 {99}"]
+    assert c["nodes"].to_list()[0][:3] == b"\xb1NS"
+    newer = pl.Series("nodes", [b[:3] + b"\x02" + b[4:] for b in c["nodes"].to_list()], dtype=pl.Binary)
+    with pytest.raises(InvalidSetColumnError, match="newer"):
+        popcnt(newer)
+
+def test_bigsets(simple_graph, empty_bigset, tmp_path):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt").sort("label")
+    # operations mixing SmallSet and BigSet produce a BigSet
+    big = c.with_column(pl.col("nodes").set.symmetric_difference(empty_bigset).alias("nodes"))
+    assert big["nodes"].to_list()[0][4] == 1
+    flat = big.select(pl.col("nodes").set.flatten(simple_graph).alias("members"))
+    assert flat["members"].to_list() == [[0, 1, 2], [99]]
+    assert simple_graph.nodes(big).frame_equal(simple_graph.nodes(c))
+    issues = validate(simple_graph, big)
+    assert issues.filter(pl.col("issue") == "unexpected_bigset")["label"].to_list() == ["1", "2"]
+    assert issues.filter(pl.col("issue") == "unknown_node").height == 0
+    out = str(tmp_path / "clus.txt")
+    write_membership(simple_graph, big, out)
+    assert read_membership(simple_graph, out).sort("label")["n"].to_list() == [3, 1]
+
+def test_mixed_union(simple_graph, empty_bigset):
+    c = read_membership_series(
+        simple_graph, pl.Series([0, 99, 1, 2], dtype=pl.UInt32), pl.Series([1, 1, 2, 2], dtype=pl.UInt32)
+    ).sort("label")
+    big = c.select(pl.col("nodes").set.symmetric_difference(empty_bigset).alias("nodes"))
+    mixed = pl.concat([c.select("nodes").head(1), big.tail(1)])
+    union = mixed.select(pl.col("nodes").set.union())
+    assert union["nodes"].to_list()[0][4] == 1
+    assert union.select(pl.col("nodes").set.flatten(simple_graph))["nodes"].to_list() == [[0, 1, 2, 99]]
+
+def test_edges_of(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt").sort("label")
+    c = c.with_column(simple_graph.intra_edges(pl.col("nodes")).alias("edges"))
+    edges = simple_graph.edges_of(c["edges"])
+    assert edges.to_list() == [[{"u": 0, "v": 1}, {"u": 0, "v": 2}], []]
+    ids = simple_graph.edge_ids(pl.Series([1, 0, 1]), pl.Series([0, 99, 2]))
+    assert ids.to_list() == [0, 4, None]
+    with pytest.raises(InvalidSetColumnError):
+        c.select(pl.col("edges").set.flatten(simple_graph))
+    with pytest.raises(InvalidSetColumnError):
+        simple_graph.edges_of(c["nodes"])
+    with pytest.raises(InvalidSetColumnError):
+        c.select(pl.col("nodes").set.intersection(pl.col("edges")))
+    with pytest.raises(InvalidSetColumnError):
+        pl.concat([c.select("nodes"), c.select(pl.col("edges").alias("nodes"))]).select(pl.col("nodes").set.union())
+
+def test_pick_members(simple_graph):
+    c = read_membership(simple_graph, "resources/discont_graph.clus.txt").sort("label")
+    picked = c.select([
+        pl.col("nodes").set.min(simple_graph).alias("min"),
+        pl.col("nodes").set.max(simple_graph).alias("max"),
+        pl.col("nodes").set.select(simple_graph, 1).alias("second"),
+    ])
+    assert picked["min"].to_list() == [0, 99]
+    assert picked["max"].to_list() == [2, 99]
+    assert picked["second"].to_list() == [1, None]
+    sample = lambda seed: c.select(pl.col("nodes").set.sample(simple_graph, 2, seed=seed))["nodes"].to_list()
+    first, single = sample(42)
+    assert len(first) == 2 and set(first) <= {0, 1, 2}
+    assert single == [99]
+    assert sample(42) == [first, single]
+    wide = read_membership_series(
+        simple_graph,
+        pl.Series([0, 1, 2, 3, 4, 99, 1, 2, 3, 4], dtype=pl.UInt32),
+        pl.Series([1] * 6 + [2] * 4, dtype=pl.UInt32),
+    ).sort("label")
+    # samples are fixed for a given seed, across releases
+    wide_sample = lambda seed: wide.select(pl.col("nodes").set.sample(simple_graph, 2, seed=seed))["nodes"].to_list()
+    assert wide_sample(42) == [[1, 4], [1, 2]]
+    assert wide_sample(7) == [[1, 4], [1, 4]]